@@ -19,6 +19,8 @@ struct Point {
 }
 
 impl Spatial2D for Point {
+    type Scalar = f32;
+
     fn x(&self) -> f32 {
         self.position.x()
     }
@@ -60,11 +62,13 @@ fn main() {
 
         if let Some(Button::Mouse(button)) = event.press_args() {
             if button == MouseButton::Left {
-                keys.push(quadtree.insert(Point::new(
+                if let Ok(key) = quadtree.insert(Point::new(
                     cursor[0] as f32, cursor[1] as f32,
                     [rng.gen::<f32>() * VEL_MULT,
                         rng.gen::<f32>() * VEL_MULT]
-                )));
+                )) {
+                    keys.push(key);
+                }
             } else if button == MouseButton::Right {
                 if let Some(key) = keys.pop() {
                     quadtree.remove(key);
@@ -92,23 +96,24 @@ fn main() {
 
         event.mouse_cursor(|pos| cursor = pos);
 
-        //let bounds = quadtree.bounds;
-
-//        if run {
-//            for point in quadtree.values_mut() {
-//                point.position.x += point.velocity[0] * dt;
-//                point.position.y += point.velocity[1] * dt;
-//
-//                if point.position.x >= bounds.x_max || point.position.x <= bounds.x_min {
-//                    point.velocity[0] = -point.velocity[0]
-//                }
-//
-//                if point.position.y >= bounds.y_max || point.position.y <= bounds.y_min {
-//                    point.velocity[1] = -point.velocity[1]
-//                }
-//            }
-//            quadtree.rebuild_tree();
-//        }
+        let bounds = Bounds::new(0., SCREEN_WIDTH as f32, 0., SCREEN_HEIGHT as f32);
+
+        if run {
+            quadtree.move_all(|_, point| {
+                point.position.x += point.velocity[0] * dt;
+                point.position.y += point.velocity[1] * dt;
+
+                if point.position.x >= bounds.x_max || point.position.x <= bounds.x_min {
+                    point.velocity[0] = -point.velocity[0];
+                    point.position.x = point.position.x.max(bounds.x_min).min(bounds.x_max);
+                }
+
+                if point.position.y >= bounds.y_max || point.position.y <= bounds.y_min {
+                    point.velocity[1] = -point.velocity[1];
+                    point.position.y = point.position.y.max(bounds.y_min).min(bounds.y_max);
+                }
+            });
+        }
 
         window.draw_2d(&event, |context, graphics, _device| {
             clear([1.0; 4], graphics);
@@ -19,6 +19,8 @@ struct Point {
 }
 
 impl Spatial2D for Point {
+    type Scalar = f32;
+
     fn x(&self) -> f32 {
         self.position.x()
     }
@@ -57,14 +59,17 @@ fn main() {
 
         if let Some(Button::Mouse(button)) = event.press_args() {
             if button == MouseButton::Left {
-                quadtree.insert(Point::new(
+                let _ = quadtree.insert(Point::new(
                     cursor[0] as f32, cursor[1] as f32,
                     [rng.gen::<f32>() * VEL_MULT,
                         rng.gen::<f32>() * VEL_MULT]
                 ));
             } else if button == MouseButton::Right {
-                for p in quadtree.within(&cursor, CURSOR_RADIUS as f32) {
-                    quadtree.remove(p);
+                let points: Vec<Point> = quadtree
+                    .within(&[cursor[0] as f32, cursor[1] as f32], CURSOR_RADIUS as f32)
+                    .into_iter().copied().collect();
+                for p in points {
+                    quadtree.remove(&p);
                 }
             }
 
@@ -92,19 +97,20 @@ fn main() {
         let bounds = quadtree.bounds;
 
         if run {
-            for point in quadtree.values_mut() {
+            quadtree.move_all(|_, point| {
                 point.position.x += point.velocity[0] * dt;
                 point.position.y += point.velocity[1] * dt;
 
                 if point.position.x >= bounds.x_max || point.position.x <= bounds.x_min {
-                    point.velocity[0] = -point.velocity[0]
+                    point.velocity[0] = -point.velocity[0];
+                    point.position.x = point.position.x.max(bounds.x_min).min(bounds.x_max);
                 }
 
                 if point.position.y >= bounds.y_max || point.position.y <= bounds.y_min {
-                    point.velocity[1] = -point.velocity[1]
+                    point.velocity[1] = -point.velocity[1];
+                    point.position.y = point.position.y.max(bounds.y_min).min(bounds.y_max);
                 }
-            }
-            quadtree.rebuild_tree();
+            });
         }
 
         window.draw_2d(&event, |context, graphics, _device| {
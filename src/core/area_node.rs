@@ -0,0 +1,79 @@
+use crate::core::{SpatialKey, Bounds, Scalar, Quadrant};
+
+/// Node of the trees over objects covering an area, holding the keys of
+/// the objects that belong to it but to none of its children
+#[derive(Debug, Default)]
+pub(crate) struct AreaNode {
+    pub items: Vec<SpatialKey>,
+    pub children: Option<Box<AreaBranch>>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Default)]
+pub(crate) struct AreaBranch {
+    TL: AreaNode,
+    TR: AreaNode,
+    BL: AreaNode,
+    BR: AreaNode,
+}
+
+impl AreaBranch {
+    /// Returns each child node paired with the quadrant it covers
+    pub fn children(&self) -> [(&AreaNode, Quadrant); 4] {
+        [
+            (&self.TL, Quadrant::TL),
+            (&self.TR, Quadrant::TR),
+            (&self.BL, Quadrant::BL),
+            (&self.BR, Quadrant::BR),
+        ]
+    }
+
+    pub fn child_mut(&mut self, quadrant: Quadrant) -> &mut AreaNode {
+        match quadrant {
+            Quadrant::TL => &mut self.TL,
+            Quadrant::TR => &mut self.TR,
+            Quadrant::BL => &mut self.BL,
+            Quadrant::BR => &mut self.BR,
+        }
+    }
+}
+
+impl AreaNode {
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty() && self.children.is_none()
+    }
+
+    /// Removes `key` from the items of this node
+    pub fn remove_item(&mut self, key: SpatialKey) -> bool {
+        match self.items.iter().position(|k| *k == key) {
+            Some(idx) => {
+                self.items.remove(idx);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Drops the children once none of them hold objects
+    pub fn prune(&mut self) {
+        let empty = self.children.as_ref()
+            .is_some_and(|branch| branch.children().iter().all(|(child, _)| child.is_empty()));
+        if empty {
+            self.children = None;
+        }
+    }
+
+    /// Collects the bounds of this node and of every child below it
+    /// that holds objects
+    pub fn bounds<S>(&self, vec: &mut Vec<Bounds<S>>, curr_bound: Bounds<S>)
+        where S: Scalar {
+        vec.push(curr_bound);
+        if let Some(branch) = &self.children {
+            for &(child, quadrant) in branch.children().iter() {
+                if !child.is_empty() {
+                    child.bounds(vec, curr_bound.sub_bound(quadrant));
+                }
+            }
+        }
+    }
+}
@@ -1,23 +1,50 @@
-use crate::core::{Quadrant, Spatial2D};
+use crate::core::{Quadrant, Scalar, Spatial2D};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct Bounds {
-    pub x_min: f32,
-    pub x_max: f32,
-    pub y_min: f32,
-    pub y_max: f32,
+pub struct Bounds<S = f32> {
+    pub x_min: S,
+    pub x_max: S,
+    pub y_min: S,
+    pub y_max: S,
 }
 
-impl Bounds {
-    pub fn new(x_min: f32, x_max: f32, y_min: f32, y_max: f32) -> Bounds {
+impl<S> Bounds<S>
+    where S: Scalar {
+    pub fn new(x_min: S, x_max: S, y_min: S, y_max: S) -> Self {
         Self {
             x_min, x_max, y_min, y_max,
         }
     }
 
-    pub fn sub_bound(&self, quadrant: Quadrant) -> Bounds {
+    /// Returns the smallest bounds holding every point that is a number,
+    /// widened to at least one unit along each axis so they can be doubled
+    pub(crate) fn around_points<P>(points: &[P]) -> Self
+        where P: Spatial2D<Scalar = S> {
+        let mut ret: Option<Self> = None;
+        for (x, y) in points.iter().map(|p| p.pos()) {
+            if x.partial_cmp(&x).is_none() || y.partial_cmp(&y).is_none() {
+                continue;
+            }
+            ret = Some(match ret {
+                Some(b) => Bounds::new(
+                    b.x_min.partial_min(x), b.x_max.partial_max(x),
+                    b.y_min.partial_min(y), b.y_max.partial_max(y)
+                ),
+                None => Bounds::new(x, x, y, y)
+            });
+        }
+        let mut ret = match ret {
+            Some(bounds) => bounds,
+            None => return Bounds::default()
+        };
+        ret.x_max = ret.x_max.partial_max(ret.x_min + S::ONE);
+        ret.y_max = ret.y_max.partial_max(ret.y_min + S::ONE);
+        ret
+    }
+
+    pub fn sub_bound(&self, quadrant: Quadrant) -> Self {
         let Bounds {x_min, x_max, y_min, y_max} = *self;
-        let (half_x, half_y) = self.half_bounds();
+        let (half_x, half_y) = self.center();
         match quadrant {
             Quadrant::TL => Bounds::new(x_min, half_x, y_min, half_y),
             Quadrant::TR => Bounds::new(half_x, x_max, y_min, half_y),
@@ -26,27 +53,40 @@ impl Bounds {
         }
     }
 
-    pub fn half_bounds(&self) -> (f32, f32) {
-        let Bounds {x_min, x_max, y_min, y_max} = self;
-        let (width, height) = (x_max - x_min, y_max - y_min);
-        (x_min + (width / 2.), y_min + (height / 2.))
+    pub fn width(&self) -> S {
+        self.x_max - self.x_min
+    }
+
+    pub fn height(&self) -> S {
+        self.y_max - self.y_min
+    }
+
+    pub fn area(&self) -> S {
+        self.width() * self.height()
+    }
+
+    /// Returns the point halfway between the edges of these bounds
+    pub fn center(&self) -> (S, S) {
+        (self.x_min + self.width().half(), self.y_min + self.height().half())
     }
 
-    pub fn is_point_within(&self, point: &dyn Spatial2D) -> bool {
+    /// Returns true if the point lies inside or on the edge of these bounds
+    pub fn contains_point(&self, point: &dyn Spatial2D<Scalar = S>) -> bool {
         let (x, y) = point.pos();
         x >= self.x_min && x <= self.x_max && y >= self.y_min && y <= self.y_max
     }
 
-    fn is_bound_within(&self, other_bound: Bounds) -> bool {
-        self.x_min <= other_bound.x_min &&
-        self.x_max >= other_bound.x_max &&
-        self.y_min <= other_bound.y_min &&
-        self.y_max >= other_bound.y_max
+    /// Returns true if every point of `other` lies inside these bounds
+    pub fn contains_bounds(&self, other: Self) -> bool {
+        self.x_min <= other.x_min &&
+        self.x_max >= other.x_max &&
+        self.y_min <= other.y_min &&
+        self.y_max >= other.y_max
     }
 
-    pub fn find_quadrant(&self, point: &dyn Spatial2D) -> Quadrant {
+    pub fn find_quadrant(&self, point: &dyn Spatial2D<Scalar = S>) -> Quadrant {
         let (x, y) = point.pos();
-        let (half_x, half_y) = self.half_bounds();
+        let (half_x, half_y) = self.center();
         if x <= half_x {
             if y <= half_y {
                 Quadrant::TL
@@ -62,7 +102,66 @@ impl Bounds {
         }
     }
 
-    pub fn truncate(&mut self, other: Bounds) {
+    /// Returns the quadrant holding `point` along with its bounds, same as
+    /// [`Bounds::find_quadrant`] followed by [`Bounds::sub_bound`]
+    pub fn split_towards(&self, point: &dyn Spatial2D<Scalar = S>) -> (Quadrant, Self) {
+        let (x, y) = point.pos();
+        let (half_x, half_y) = self.center();
+        let mut ret = *self;
+        if x <= half_x { ret.x_max = half_x } else { ret.x_min = half_x }
+        if y <= half_y { ret.y_max = half_y } else { ret.y_min = half_y }
+        let quadrant = match (x <= half_x, y <= half_y) {
+            (true, true) => Quadrant::TL,
+            (true, false) => Quadrant::BL,
+            (false, true) => Quadrant::TR,
+            (false, false) => Quadrant::BR,
+        };
+        (quadrant, ret)
+    }
+
+    /// Returns the quadrant whose sub bounds fully contain `other`, or none
+    /// if it crosses the center lines. Agrees with [`Bounds::find_quadrant`]
+    /// for bounds lying on a center line
+    pub fn enclosing_quadrant(&self, other: Self) -> Option<Quadrant> {
+        let quadrant = self.find_quadrant(&[other.x_min, other.y_min]);
+        if self.sub_bound(quadrant).contains_bounds(other) {
+            Some(quadrant)
+        } else {
+            None
+        }
+    }
+
+    /// Returns bounds twice the size that extend towards `point`, along
+    /// with the quadrant of them these bounds take up. Returns none if
+    /// the bounds can not grow any further or the point is not a number
+    pub fn double_towards(&self, point: &dyn Spatial2D<Scalar = S>) -> Option<(Self, Quadrant)> {
+        let (x, y) = point.pos();
+        let (half_x, half_y) = self.center();
+        if x.partial_cmp(&half_x).is_none() || y.partial_cmp(&half_y).is_none() {
+            return None;
+        }
+        let (width, height) = (self.width(), self.height());
+        let grow_left = x < half_x;
+        let grow_up = y < half_y;
+        let bounds = Bounds::new(
+            if grow_left { self.x_min - width } else { self.x_min },
+            if grow_left { self.x_max } else { self.x_max + width },
+            if grow_up { self.y_min - height } else { self.y_min },
+            if grow_up { self.y_max } else { self.y_max + height },
+        );
+        if !(bounds.width() > width && bounds.height() > height) {
+            return None;
+        }
+        let quadrant = match (grow_left, grow_up) {
+            (true, true) => Quadrant::BR,
+            (false, true) => Quadrant::BL,
+            (true, false) => Quadrant::TR,
+            (false, false) => Quadrant::TL,
+        };
+        Some((bounds, quadrant))
+    }
+
+    pub fn truncate(&mut self, other: Self) {
         if self.x_min < other.x_min {
             self.x_min = other.x_min;
         }
@@ -77,16 +176,190 @@ impl Bounds {
         }
     }
 
-    pub fn overlaps(&self, other: Bounds) -> bool {
-        ((self.x_min > other.x_min && self.x_min < other.x_max) ||
-        (self.x_max > other.x_min && self.x_max < other.x_max)) &&
-        ((self.y_min > other.y_min && self.y_min < other.y_max) ||
-        (self.y_max > other.y_min && self.y_max < other.y_max))
+    /// Returns the shortest distance from the point to any part of these
+    /// bounds, or zero if the point lies inside them
+    pub fn distance_to_point(&self, point: &dyn Spatial2D<Scalar = S>) -> S {
+        S::from_squared(self.squared_distance_to_point(point))
+    }
+
+    /// Squared version of [`Bounds::distance_to_point`], which is exact for integer types
+    pub fn squared_distance_to_point(&self, point: &dyn Spatial2D<Scalar = S>) -> S::Squared {
+        let (x, y) = point.pos();
+        let nearest = [
+            x.partial_max(self.x_min).partial_min(self.x_max),
+            y.partial_max(self.y_min).partial_min(self.y_max),
+        ];
+        S::squared_distance(&[x, y], &nearest)
+    }
+
+    /// Returns true if the two bounds share any point, including
+    /// bounds that only touch along an edge or corner
+    pub fn intersects(&self, other: Self) -> bool {
+        self.x_min <= other.x_max && other.x_min <= self.x_max &&
+        self.y_min <= other.y_max && other.y_min <= self.y_max
+    }
+
+    /// Returns true if the two bounds share a region of non-zero area.
+    /// Unlike [`Bounds::intersects`], touching edges do not count
+    pub fn overlaps(&self, other: Self) -> bool {
+        self.x_min < other.x_max && other.x_min < self.x_max &&
+        self.y_min < other.y_max && other.y_min < self.y_max
+    }
+
+    /// Returns the region shared by both bounds, or none if they
+    /// do not intersect
+    pub fn intersection(&self, other: Self) -> Option<Self> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let mut ret = *self;
+        ret.truncate(other);
+        Some(ret)
+    }
+
+    /// Returns the smallest bounds containing both bounds
+    pub fn union(&self, other: Self) -> Self {
+        Bounds::new(
+            self.x_min.partial_min(other.x_min),
+            self.x_max.partial_max(other.x_max),
+            self.y_min.partial_min(other.y_min),
+            self.y_max.partial_max(other.y_max),
+        )
+    }
+
+    /// Returns these bounds grown outward by `amount` on every side
+    pub fn expand_by(&self, amount: S) -> Self {
+        Bounds::new(
+            self.x_min - amount,
+            self.x_max + amount,
+            self.y_min - amount,
+            self.y_max + amount,
+        )
     }
 }
 
-impl Default for Bounds {
+impl<S> Default for Bounds<S>
+    where S: Scalar {
     fn default() -> Self {
-        Bounds::new(0., 1., 0., 1.)
+        Bounds::new(S::ZERO, S::ONE, S::ZERO, S::ONE)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bounds;
+    use crate::core::{Point2D, Quadrant};
+
+    #[test]
+    fn test_intersects() {
+        let bounds = Bounds::new(0., 10., 0., 10.);
+
+        assert!(bounds.intersects(bounds));
+        assert!(bounds.intersects(Bounds::new(2., 4., 2., 4.)));
+        assert!(Bounds::new(2., 4., 2., 4.).intersects(bounds));
+        assert!(bounds.intersects(Bounds::new(-5., 15., 4., 6.)));
+        assert!(bounds.intersects(Bounds::new(10., 20., 0., 10.)));
+        assert!(bounds.intersects(Bounds::new(10., 20., 10., 20.)));
+        assert!(!bounds.intersects(Bounds::new(10.5, 20., 0., 10.)));
+
+        assert!(bounds.overlaps(bounds));
+        assert!(bounds.overlaps(Bounds::new(-5., 15., 4., 6.)));
+        assert!(!bounds.overlaps(Bounds::new(10., 20., 0., 10.)));
+    }
+
+    #[test]
+    fn test_containment() {
+        let bounds = Bounds::new(0., 10., 0., 10.);
+
+        assert!(bounds.contains_bounds(bounds));
+        assert!(bounds.contains_bounds(Bounds::new(2., 4., 2., 4.)));
+        assert!(!bounds.contains_bounds(Bounds::new(2., 14., 2., 4.)));
+
+        assert!(bounds.contains_point(&Point2D::new(0., 10.)));
+        assert!(!bounds.contains_point(&Point2D::new(-0.1, 5.)));
+    }
+
+    #[test]
+    fn test_combination() {
+        let a = Bounds::new(0., 10., 0., 10.);
+        let b = Bounds::new(5., 20., -5., 5.);
+
+        assert_eq!(a.intersection(b), Some(Bounds::new(5., 10., 0., 5.)));
+        assert_eq!(a.intersection(Bounds::new(11., 12., 0., 1.)), None);
+        assert_eq!(a.union(b), Bounds::new(0., 20., -5., 10.));
+        assert_eq!(a.expand_by(1.), Bounds::new(-1., 11., -1., 11.));
+    }
+
+    #[test]
+    fn test_measurements() {
+        let bounds = Bounds::new(-2., 2., 1., 7.);
+
+        assert_eq!(bounds.width(), 4.);
+        assert_eq!(bounds.height(), 6.);
+        assert_eq!(bounds.area(), 24.);
+        assert_eq!(bounds.center(), (0., 4.));
+
+        assert_eq!(bounds.distance_to_point(&Point2D::new(0., 4.)), 0.);
+        assert_eq!(bounds.distance_to_point(&Point2D::new(5., 4.)), 3.);
+        assert_eq!(bounds.distance_to_point(&Point2D::new(5., 11.)), 5.);
+    }
+
+    #[test]
+    fn test_scalar_types() {
+        let bounds = Bounds::new(0_i32, 10, 0, 10);
+        assert_eq!(bounds.center(), (5, 5));
+        assert_eq!(bounds.sub_bound(Quadrant::BR), Bounds::new(5, 10, 5, 10));
+        assert_eq!(bounds.distance_to_point(&[13, 14]), 5);
+
+        // coordinates that f32 cannot tell apart
+        let bounds = Bounds::new(1e8_f64, 1e8 + 1., 0., 1.);
+        assert!(bounds.contains_point(&[1e8 + 0.5, 0.5]));
+        assert_eq!(bounds.center().0, 1e8 + 0.5);
+    }
+
+    #[test]
+    fn test_enclosing_quadrant() {
+        let bounds = Bounds::new(0., 10., 0., 10.);
+
+        assert_eq!(bounds.enclosing_quadrant(Bounds::new(6., 8., 1., 2.)), Some(Quadrant::TR));
+        assert_eq!(bounds.enclosing_quadrant(Bounds::new(4., 6., 1., 2.)), None);
+        // bounds on the center line belong where points on it do
+        assert_eq!(bounds.enclosing_quadrant(Bounds::new(5., 5., 7., 8.)), Some(Quadrant::BL));
+    }
+
+    #[test]
+    fn test_split_towards() {
+        let bounds = Bounds::new(0., 10., 0., 10.);
+        for point in [[2., 7.], [5., 5.], [9., 1.], [5., 10.]].iter() {
+            let quadrant = bounds.find_quadrant(point);
+            assert_eq!(bounds.split_towards(point), (quadrant, bounds.sub_bound(quadrant)));
+        }
+    }
+
+    #[test]
+    fn test_double_towards() {
+        let bounds = Bounds::new(0., 10., 0., 10.);
+
+        let (grown, quadrant) = bounds.double_towards(&[-3., 15.]).unwrap();
+        assert_eq!(grown, Bounds::new(-10., 10., 0., 20.));
+        assert_eq!(quadrant, Quadrant::TR);
+        assert_eq!(grown.sub_bound(quadrant), bounds);
+
+        let (grown, quadrant) = bounds.double_towards(&[30., -30.]).unwrap();
+        assert_eq!(grown, Bounds::new(0., 20., -10., 10.));
+        assert_eq!(grown.sub_bound(quadrant), bounds);
+
+        assert_eq!(Bounds::new(0., 0., 0., 10.).double_towards(&[5., 5.]), None);
+        assert_eq!(bounds.double_towards(&[f32::NAN, 5.]), None);
+    }
+
+    #[test]
+    fn test_around_points() {
+        let points = [[3., 8.], [f32::NAN, 0.], [-2., 9.], [4., 12.]];
+        assert_eq!(Bounds::around_points(&points), Bounds::new(-2., 4., 8., 12.));
+
+        // a single point still has room to grow
+        assert_eq!(Bounds::around_points(&[[5., 5.]]), Bounds::new(5., 6., 5., 6.));
+        assert_eq!(Bounds::around_points::<[f32; 2]>(&[]), Bounds::default());
+    }
+}
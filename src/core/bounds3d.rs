@@ -0,0 +1,176 @@
+use crate::core::{Octant, Scalar, Spatial3D};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds3D<S = f32> {
+    pub x_min: S,
+    pub x_max: S,
+    pub y_min: S,
+    pub y_max: S,
+    pub z_min: S,
+    pub z_max: S,
+}
+
+impl<S> Bounds3D<S>
+    where S: Scalar {
+    pub fn new(x_min: S, x_max: S, y_min: S, y_max: S, z_min: S, z_max: S) -> Self {
+        Self {
+            x_min, x_max, y_min, y_max, z_min, z_max,
+        }
+    }
+
+    pub fn sub_bound(&self, octant: Octant) -> Self {
+        let Bounds3D {x_min, x_max, y_min, y_max, z_min, z_max} = *self;
+        let (half_x, half_y, half_z) = self.center();
+        match octant {
+            Octant::TLN => Bounds3D::new(x_min, half_x, y_min, half_y, z_min, half_z),
+            Octant::TRN => Bounds3D::new(half_x, x_max, y_min, half_y, z_min, half_z),
+            Octant::BLN => Bounds3D::new(x_min, half_x, half_y, y_max, z_min, half_z),
+            Octant::BRN => Bounds3D::new(half_x, x_max, half_y, y_max, z_min, half_z),
+            Octant::TLF => Bounds3D::new(x_min, half_x, y_min, half_y, half_z, z_max),
+            Octant::TRF => Bounds3D::new(half_x, x_max, y_min, half_y, half_z, z_max),
+            Octant::BLF => Bounds3D::new(x_min, half_x, half_y, y_max, half_z, z_max),
+            Octant::BRF => Bounds3D::new(half_x, x_max, half_y, y_max, half_z, z_max),
+        }
+    }
+
+    pub fn width(&self) -> S {
+        self.x_max - self.x_min
+    }
+
+    pub fn height(&self) -> S {
+        self.y_max - self.y_min
+    }
+
+    pub fn depth(&self) -> S {
+        self.z_max - self.z_min
+    }
+
+    pub fn volume(&self) -> S {
+        self.width() * self.height() * self.depth()
+    }
+
+    /// Returns the point halfway between the faces of these bounds
+    pub fn center(&self) -> (S, S, S) {
+        (
+            self.x_min + self.width().half(),
+            self.y_min + self.height().half(),
+            self.z_min + self.depth().half(),
+        )
+    }
+
+    /// Returns true if the point lies inside or on a face of these bounds
+    pub fn contains_point(&self, point: &dyn Spatial3D<Scalar = S>) -> bool {
+        let (x, y, z) = point.loc();
+        x >= self.x_min && x <= self.x_max &&
+        y >= self.y_min && y <= self.y_max &&
+        z >= self.z_min && z <= self.z_max
+    }
+
+    /// Returns true if every point of `other` lies inside these bounds
+    pub fn contains_bounds(&self, other: Self) -> bool {
+        self.x_min <= other.x_min && self.x_max >= other.x_max &&
+        self.y_min <= other.y_min && self.y_max >= other.y_max &&
+        self.z_min <= other.z_min && self.z_max >= other.z_max
+    }
+
+    /// Returns the octant holding the point. Points on a center plane
+    /// belong to the lower half
+    pub fn find_octant(&self, point: &dyn Spatial3D<Scalar = S>) -> Octant {
+        let (x, y, z) = point.loc();
+        let (half_x, half_y, half_z) = self.center();
+        match (x <= half_x, y <= half_y, z <= half_z) {
+            (true, true, true) => Octant::TLN,
+            (false, true, true) => Octant::TRN,
+            (true, false, true) => Octant::BLN,
+            (false, false, true) => Octant::BRN,
+            (true, true, false) => Octant::TLF,
+            (false, true, false) => Octant::TRF,
+            (true, false, false) => Octant::BLF,
+            (false, false, false) => Octant::BRF,
+        }
+    }
+
+    /// Returns the shortest distance from the point to any part of these
+    /// bounds, or zero if the point lies inside them
+    pub fn distance_to_point(&self, point: &dyn Spatial3D<Scalar = S>) -> S {
+        S::from_squared(self.squared_distance_to_point(point))
+    }
+
+    /// Squared version of [`Bounds3D::distance_to_point`], which is exact for integer types
+    pub fn squared_distance_to_point(&self, point: &dyn Spatial3D<Scalar = S>) -> S::Squared {
+        let (x, y, z) = point.loc();
+        let nearest = [
+            x.partial_max(self.x_min).partial_min(self.x_max),
+            y.partial_max(self.y_min).partial_min(self.y_max),
+            z.partial_max(self.z_min).partial_min(self.z_max),
+        ];
+        S::squared_distance(&[x, y, z], &nearest)
+    }
+
+    /// Returns true if the two bounds share any point, including
+    /// bounds that only touch along a face, edge or corner
+    pub fn intersects(&self, other: Self) -> bool {
+        self.x_min <= other.x_max && other.x_min <= self.x_max &&
+        self.y_min <= other.y_max && other.y_min <= self.y_max &&
+        self.z_min <= other.z_max && other.z_min <= self.z_max
+    }
+
+    /// Returns the region shared by both bounds, or none if they
+    /// do not intersect
+    pub fn intersection(&self, other: Self) -> Option<Self> {
+        if !self.intersects(other) {
+            return None;
+        }
+        Some(Bounds3D::new(
+            self.x_min.partial_max(other.x_min),
+            self.x_max.partial_min(other.x_max),
+            self.y_min.partial_max(other.y_min),
+            self.y_max.partial_min(other.y_max),
+            self.z_min.partial_max(other.z_min),
+            self.z_max.partial_min(other.z_max),
+        ))
+    }
+}
+
+impl<S> Default for Bounds3D<S>
+    where S: Scalar {
+    fn default() -> Self {
+        Bounds3D::new(S::ZERO, S::ONE, S::ZERO, S::ONE, S::ZERO, S::ONE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bounds3D;
+    use crate::core::{Octant, OCTANTS, Point3D};
+
+    #[test]
+    fn test_octants() {
+        let bounds = Bounds3D::new(0., 10., 0., 10., 0., 10.);
+
+        for (idx, octant) in OCTANTS.iter().enumerate() {
+            assert_eq!(*octant as usize, idx);
+            let (x, y, z) = bounds.sub_bound(*octant).center();
+            assert_eq!(bounds.find_octant(&Point3D::new(x, y, z)), *octant);
+        }
+        assert_eq!(bounds.sub_bound(Octant::BRF), Bounds3D::new(5., 10., 5., 10., 5., 10.));
+        assert_eq!(bounds.find_octant(&Point3D::new(5., 5., 5.)), Octant::TLN);
+    }
+
+    #[test]
+    fn test_measurements() {
+        let bounds = Bounds3D::new(-2., 2., 1., 7., 0., 1.);
+
+        assert_eq!(bounds.volume(), 24.);
+        assert_eq!(bounds.center(), (0., 4., 0.5));
+        assert!(bounds.contains_point(&Point3D::new(2., 1., 0.5)));
+        assert!(!bounds.contains_point(&Point3D::new(0., 4., 1.5)));
+
+        assert_eq!(bounds.distance_to_point(&Point3D::new(0., 4., 0.5)), 0.);
+        assert_eq!(bounds.distance_to_point(&Point3D::new(5., 4., 5.)), 5.);
+        assert_eq!(
+            bounds.intersection(Bounds3D::new(0., 5., 0., 5., 0., 5.)),
+            Some(Bounds3D::new(0., 2., 1., 5., 0., 1.))
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::cmp::Ordering;
+
+/// Entry in a best-first search queue, either a node that still
+/// has to be expanded or an item that has been reached
+pub(crate) enum CandidateEntry<N, I> {
+    Node(N),
+    Item(I),
+}
+
+/// Best-first search queue element, ordered so that a `BinaryHeap`
+/// pops the smallest distance first
+pub(crate) struct Candidate<N, I, D = f32> {
+    pub distance: D,
+    pub entry: CandidateEntry<N, I>,
+}
+
+impl<N, I, D> Candidate<N, I, D> {
+    pub fn node(distance: D, node: N) -> Self {
+        Candidate { distance, entry: CandidateEntry::Node(node) }
+    }
+
+    pub fn item(distance: D, item: I) -> Self {
+        Candidate { distance, entry: CandidateEntry::Item(item) }
+    }
+}
+
+impl<N, I, D> PartialEq for Candidate<N, I, D>
+    where D: PartialOrd {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<N, I, D> Eq for Candidate<N, I, D>
+    where D: PartialOrd {}
+
+impl<N, I, D> PartialOrd for Candidate<N, I, D>
+    where D: PartialOrd {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N, I, D> Ord for Candidate<N, I, D>
+    where D: PartialOrd {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed for a min-heap; items win ties against nodes so they
+        // are returned before an equally distant node is expanded
+        other.distance.partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| match (&self.entry, &other.entry) {
+                (CandidateEntry::Item(_), CandidateEntry::Node(_)) => Ordering::Greater,
+                (CandidateEntry::Node(_), CandidateEntry::Item(_)) => Ordering::Less,
+                _ => Ordering::Equal
+            })
+    }
+}
@@ -1,17 +1,74 @@
 use std::fmt;
 use std::error;
+use crate::core::{Bounds, Bounds3D, Scalar};
 
-#[derive(Debug)]
+/// Error returned by the fallible operations of the spatial containers
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum SpatialError {
-    QuadtreeInsertError,
-    QuadtreeKeyOverflowError,
+    /// The position lies outside of the region covered by the tree.
+    /// Coordinates are widened to `f64` with the bounds given as
+    /// `(min, max)` pairs per axis. Trees in the plane leave z at zero
+    OutOfBounds {
+        point: [f64; 3],
+        bounds: [(f64, f64); 3],
+    },
+    /// The node is already at the deepest level a key can describe
+    DepthExceeded,
+    /// Every overflow key of a node at the deepest level is in use
+    KeyOverflow,
+    /// The key is already used by another element
+    DuplicateKey,
 }
 
 pub type Result<T> = std::result::Result<T, SpatialError>;
 
+impl SpatialError {
+    pub(crate) fn out_of_bounds<S>(point: (S, S), bounds: Bounds<S>) -> Self
+        where S: Scalar {
+        SpatialError::OutOfBounds {
+            point: [point.0.to_f64(), point.1.to_f64(), 0.],
+            bounds: [
+                (bounds.x_min.to_f64(), bounds.x_max.to_f64()),
+                (bounds.y_min.to_f64(), bounds.y_max.to_f64()),
+                (0., 0.),
+            ],
+        }
+    }
+
+    pub(crate) fn out_of_bounds_3d<S>(point: (S, S, S), bounds: Bounds3D<S>) -> Self
+        where S: Scalar {
+        SpatialError::OutOfBounds {
+            point: [point.0.to_f64(), point.1.to_f64(), point.2.to_f64()],
+            bounds: [
+                (bounds.x_min.to_f64(), bounds.x_max.to_f64()),
+                (bounds.y_min.to_f64(), bounds.y_max.to_f64()),
+                (bounds.z_min.to_f64(), bounds.z_max.to_f64()),
+            ],
+        }
+    }
+
+    /// Reports the first corner of `region` that lies outside of `bounds`
+    pub(crate) fn region_out_of_bounds<S>(region: Bounds<S>, bounds: Bounds<S>) -> Self
+        where S: Scalar {
+        let corner = if bounds.contains_point(&[region.x_min, region.y_min]) {
+            (region.x_max, region.y_max)
+        } else {
+            (region.x_min, region.y_min)
+        };
+        SpatialError::out_of_bounds(corner, bounds)
+    }
+}
+
 impl fmt::Display for SpatialError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "quad tree insert errror")
+        match self {
+            SpatialError::OutOfBounds { point, bounds } => {
+                write!(f, "point {:?} is outside of the tree bounds {:?}", point, bounds)
+            }
+            SpatialError::DepthExceeded => write!(f, "maximum tree depth reached"),
+            SpatialError::KeyOverflow => write!(f, "no overflow keys left at the deepest level"),
+            SpatialError::DuplicateKey => write!(f, "key is already in use"),
+        }
     }
 }
 
@@ -22,3 +79,23 @@ impl error::Error for SpatialError {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::SpatialError;
+    use crate::core::Bounds;
+
+    #[test]
+    fn test_out_of_bounds() {
+        let bounds = Bounds::new(0., 100., 0., 100.);
+        let error = SpatialError::region_out_of_bounds(Bounds::new(90., 110., 10., 20.), bounds);
+        assert_eq!(error, SpatialError::OutOfBounds {
+            point: [110., 20., 0.],
+            bounds: [(0., 100.), (0., 100.), (0., 0.)],
+        });
+        assert_eq!(
+            error.to_string(),
+            "point [110.0, 20.0, 0.0] is outside of the tree bounds [(0.0, 100.0), (0.0, 100.0), (0.0, 0.0)]"
+        );
+        assert_eq!(SpatialError::KeyOverflow.to_string(), "no overflow keys left at the deepest level");
+    }
+}
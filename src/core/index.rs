@@ -0,0 +1,115 @@
+use crate::core::{Bounds, Result, Spatial2D, SpatialKey};
+
+/// Scalar type of the items stored in an index
+pub type IndexScalar<I> = <<I as SpatialIndex>::Item as Spatial2D>::Scalar;
+
+/// Operations shared by the point indices of this crate, so code can be
+/// written once against any of them and the index picked by a type parameter.
+///
+/// Every item is addressed by the persistent key returned when inserting it.
+/// Queries return those keys along with the items, and are named apart
+/// from the inherent queries of each tree that return the items alone
+pub trait SpatialIndex {
+    type Item: Spatial2D;
+
+    /// Inserts the item and returns a persistent key that indexes it
+    fn insert(&mut self, item: Self::Item) -> Result<SpatialKey>;
+
+    /// Removes the item at `key` and returns it
+    fn remove_by_key(&mut self, key: SpatialKey) -> Option<Self::Item>;
+
+    fn get(&self, key: SpatialKey) -> Option<&Self::Item>;
+
+    /// Returns the number of stored items
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over every item and its key, in no particular order
+    fn iter(&self) -> Box<dyn Iterator<Item = (SpatialKey, &Self::Item)> + '_>;
+
+    /// Returns an iterator over every item inside `query` and its key
+    fn query_bounds_keyed(&self, query: &Bounds<IndexScalar<Self>>)
+        -> Box<dyn Iterator<Item = (SpatialKey, &Self::Item)> + '_>;
+
+    /// Returns every item whose distance to `p` is at most `radius`,
+    /// sorted from nearest to farthest
+    fn within_keyed(&self, p: &dyn Spatial2D<Scalar = IndexScalar<Self>>, radius: IndexScalar<Self>)
+        -> Vec<(SpatialKey, &Self::Item)>;
+
+    /// Returns up to `k` items closest to `p` along with their keys and
+    /// distances, sorted from nearest to farthest
+    fn k_nearest_keyed(&self, p: &dyn Spatial2D<Scalar = IndexScalar<Self>>, k: usize)
+        -> Vec<(SpatialKey, &Self::Item, IndexScalar<Self>)>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpatialIndex;
+    use crate::core::{Bounds, Point2D};
+    use crate::linear_quadtree::LinearQuadtree;
+    use crate::pointer_quadtree::PointerQuadtree;
+
+    fn exercise<I>(mut index: I)
+        where I: SpatialIndex<Item = Point2D> {
+        let keys: Vec<_> = (0..10)
+            .map(|i| index.insert(Point2D::new(i as f32 * 10. + 5., 50.)).unwrap())
+            .collect();
+        assert!(index.insert(Point2D::new(150., 50.)).is_err());
+        assert_eq!(index.len(), 10);
+        assert_eq!(index.get(keys[3]), Some(&Point2D::new(35., 50.)));
+        assert_eq!(index.iter().count(), 10);
+
+        let mut found: Vec<_> = index.query_bounds_keyed(&Bounds::new(20., 40., 0., 100.)).collect();
+        found.sort_by_key(|&(key, _)| key);
+        assert_eq!(found, vec![(keys[2], &Point2D::new(25., 50.)), (keys[3], &Point2D::new(35., 50.))]);
+
+        assert_eq!(
+            index.within_keyed(&Point2D::new(36., 50.), 10.),
+            vec![(keys[3], &Point2D::new(35., 50.)), (keys[4], &Point2D::new(45., 50.))]
+        );
+        assert_eq!(
+            index.k_nearest_keyed(&Point2D::new(36., 50.), 2),
+            vec![(keys[3], &Point2D::new(35., 50.), 1.), (keys[4], &Point2D::new(45., 50.), 9.)]
+        );
+
+        assert_eq!(index.remove_by_key(keys[3]), Some(Point2D::new(35., 50.)));
+        assert_eq!(index.remove_by_key(keys[3]), None);
+        assert_eq!(index.get(keys[3]), None);
+        for key in keys {
+            index.remove_by_key(key);
+        }
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn test_indices() {
+        exercise(PointerQuadtree::new(Bounds::new(0., 100., 0., 100.)));
+        exercise(LinearQuadtree::new(Bounds::new(0., 100., 0., 100.)));
+    }
+
+    fn integer_distances<I>(mut index: I)
+        where I: SpatialIndex<Item = [i32; 2]> {
+        let near = index.insert([53, 54]).unwrap();
+        let far = index.insert([55, 51]).unwrap();
+        // 26 is not within a radius of 5, even though its root truncates to 5
+        assert_eq!(index.within_keyed(&[50, 50], 5), vec![(near, &[53, 54])]);
+        assert_eq!(index.k_nearest_keyed(&[50, 50], 2), vec![(near, &[53, 54], 5), (far, &[55, 51], 5)]);
+
+        // the squared distance between the corners does not fit into an i32
+        let corner = index.insert([-1_000_000_000, -1_000_000_000]).unwrap();
+        index.insert([1_000_000_000, 1_000_000_000]).unwrap();
+        assert_eq!(index.within_keyed(&[-1_000_000_000, -1_000_000_000], i32::MAX),
+                   vec![(corner, &[-1_000_000_000, -1_000_000_000]), (far, &[55, 51]), (near, &[53, 54])]);
+        assert_eq!(index.within_keyed(&[50, 50], -1), vec![]);
+    }
+
+    #[test]
+    fn test_integer_distances() {
+        let bounds = Bounds::new(-1_000_000_000, 1_000_000_000, -1_000_000_000, 1_000_000_000);
+        integer_distances(PointerQuadtree::new(bounds));
+        integer_distances(LinearQuadtree::new(bounds));
+    }
+}
@@ -0,0 +1,536 @@
+use crate::core::{Spatial2D, Spatial3D, SpatialKey, SpatialError, Bounds, Bounds3D, Scalar, Quadrant, Octant, Result};
+use hashbrown::{HashMap, HashSet};
+use slotmap::SlotMap;
+use std::fmt::Display;
+use std::hash::Hash;
+
+/// Locational key of a node in a linear tree. Implemented by the
+/// quadtree and octree keys so both trees share the logic of their maps
+pub(crate) trait LinearKey: Copy + Default + Eq + Hash + Ord + Display {
+    /// Quadrant or octant of a node within its parent
+    type Child: Copy + PartialEq + 'static;
+
+    /// Every child of a node
+    const CHILDREN: &'static [Self::Child];
+    const MAX_LEVEL: u32;
+
+    fn level(&self) -> u32;
+    fn overflow(&self) -> Option<u32>;
+    fn increment_overflow(&mut self) -> Result<()>;
+    fn without_overflow(&self) -> Self;
+    fn child(&self, child: Self::Child) -> Result<Self>;
+    fn parent(&self) -> Option<Self>;
+    /// Returns the child that the node at `level` takes on the path to this key
+    fn child_at_level(&self, level: u32) -> Self::Child;
+    /// Returns the keys of the nodes of the same size around this one
+    fn neighbors(&self) -> Vec<Self>;
+    fn is_adjacent(&self, other: &Self) -> bool;
+}
+
+/// Region of space that is split into the same children as a [`LinearKey`]
+pub(crate) trait Partition<S>: Copy {
+    type Child;
+
+    fn find_child(&self, loc: &S) -> Self::Child;
+    fn child_bounds(&self, child: Self::Child) -> Self;
+    fn contains_loc(&self, loc: &S) -> bool;
+}
+
+impl<T, S> Partition<S> for Bounds<T>
+    where T: Scalar, S: Spatial2D<Scalar = T> {
+    type Child = Quadrant;
+
+    fn find_child(&self, loc: &S) -> Quadrant {
+        self.find_quadrant(loc)
+    }
+
+    fn child_bounds(&self, quadrant: Quadrant) -> Self {
+        self.sub_bound(quadrant)
+    }
+
+    fn contains_loc(&self, loc: &S) -> bool {
+        self.contains_point(loc)
+    }
+}
+
+impl<T, S> Partition<S> for Bounds3D<T>
+    where T: Scalar, S: Spatial3D<Scalar = T> {
+    type Child = Octant;
+
+    fn find_child(&self, loc: &S) -> Octant {
+        self.find_octant(loc)
+    }
+
+    fn child_bounds(&self, octant: Octant) -> Self {
+        self.sub_bound(octant)
+    }
+
+    fn contains_loc(&self, loc: &S) -> bool {
+        self.contains_point(loc)
+    }
+}
+
+pub(crate) enum MapEntry<S> {
+    Branch,
+    /// element along with the persistent key it is indexed by,
+    /// so the key can be found from its location in constant time
+    Leaf(SpatialKey, S),
+}
+
+/// Nodes of a linear tree stored by their locational keys, along with the
+/// locational key of every element. The trees only add the shape of their
+/// space on top of it, passed in as the `space` of each method
+pub(crate) struct LinearMap<K, S>
+    where K: LinearKey {
+    pub(crate) spatial_map: HashMap<K, MapEntry<S>>,
+    pub(crate) key_map: SlotMap<SpatialKey, K>,
+}
+
+impl<K, S> Default for LinearMap<K, S>
+    where K: LinearKey {
+    fn default() -> Self {
+        Self {
+            spatial_map: HashMap::new(),
+            key_map: SlotMap::with_key()
+        }
+    }
+}
+
+impl<K, S> LinearMap<K, S>
+    where K: LinearKey {
+
+    pub(crate) fn get(&self, key: SpatialKey) -> Option<&S> {
+        self.leaf(*self.key_map.get(key)?)
+    }
+
+    pub(crate) fn get_mut(&mut self, key: SpatialKey) -> Option<&mut S> {
+        match self.spatial_map.get_mut(self.key_map.get(key)?) {
+            Some(MapEntry::Leaf(_, s)) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Returns the element stored at `key`, if it is a leaf
+    pub(crate) fn leaf(&self, key: K) -> Option<&S> {
+        match self.spatial_map.get(&key) {
+            Some(MapEntry::Leaf(_, s)) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Returns `key` followed by every overflow key in use at the same
+    /// location. Only keys at the deepest level ever have overflow keys
+    pub(crate) fn bucket_keys(&self, key: K) -> impl Iterator<Item = K> + '_ {
+        std::iter::successors(Some(key), |k| {
+            let mut next = *k;
+            next.increment_overflow().ok()?;
+            Some(next)
+        }).take_while(move |k| self.spatial_map.contains_key(k))
+    }
+
+    /// Inserts an element that lies within `space`
+    pub(crate) fn insert<B>(&mut self, loc: S, space: B) -> Result<SpatialKey>
+        where B: Partition<S, Child = K::Child> {
+        let spatial_key = self.key_map.insert(K::default());
+        match self.place(spatial_key, loc, K::default(), space) {
+            Ok(()) => Ok(spatial_key),
+            Err((e, _)) => {
+                self.key_map.remove(spatial_key);
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: SpatialKey) -> Option<S> {
+        let k = *self.key_map.get(key)?;
+        let (_, s) = self.detach(k)?;
+        self.key_map.remove(key);
+        Some(s)
+    }
+
+    /// Replaces the element at `key` with `loc`, which lies within `space`,
+    /// and returns the old element, or none if there is no element at `key`
+    pub(crate) fn update<B>(&mut self, key: SpatialKey, loc: S, space: B) -> Result<Option<S>>
+        where B: Partition<S, Child = K::Child> {
+        let k = match self.key_map.get(key) {
+            Some(k) => *k,
+            None => return Ok(None)
+        };
+
+        let level = match self.diverging_level(k, &loc, space) {
+            Some(level) => level,
+            None => {
+                if let Some(MapEntry::Leaf(_, s)) = self.spatial_map.get_mut(&k) {
+                    return Ok(Some(std::mem::replace(s, loc)));
+                }
+                return Ok(None);
+            }
+        };
+
+        let (_, old) = match self.detach(k) {
+            Some(entry) => entry,
+            None => return Ok(None)
+        };
+
+        // removal may have collapsed the common ancestor, in which case
+        // the closest branch above it is used
+        let mut start = k.without_overflow();
+        while start.level() >= level {
+            start = start.parent().unwrap();
+        }
+        while start.level() > 0 {
+            if let Some(MapEntry::Branch) = self.spatial_map.get(&start) {
+                break;
+            }
+            start = start.parent().unwrap();
+        }
+
+        match self.place(key, loc, start, space) {
+            Ok(()) => Ok(Some(old)),
+            Err((e, _)) => {
+                // put the old element back so the key stays valid
+                self.place(key, old, K::default(), space).map_err(|(e, _)| e)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Places every element that no longer lies within its node again.
+    /// Elements outside of `space` or that can not be placed again are
+    /// removed and returned with their keys
+    pub(crate) fn reindex<B>(&mut self, space: B) -> Vec<(SpatialKey, S)>
+        where B: Partition<S, Child = K::Child> {
+        let mut removed = vec![];
+        for spatial_key in self.misplaced(space) {
+            let (_, s) = match self.detach(self.key_map[spatial_key]) {
+                Some(entry) => entry,
+                None => continue
+            };
+            let s = match space.contains_loc(&s) {
+                true => match self.free_key(&s, K::default(), space) {
+                    Ok(k) => match self.insert_leaf(spatial_key, k, s) {
+                        Ok(()) => continue,
+                        Err((_, s)) => s
+                    },
+                    Err(_) => s
+                },
+                false => s
+            };
+            self.key_map.remove(spatial_key);
+            removed.push((spatial_key, s));
+        }
+        removed
+    }
+
+    /// Returns the keys of the elements that lie outside of their node
+    /// or outside of `space` altogether
+    pub(crate) fn misplaced<B>(&self, space: B) -> Vec<SpatialKey>
+        where B: Partition<S, Child = K::Child> {
+        self.key_map.iter()
+            .filter(|&(_, &k)| match self.spatial_map.get(&k) {
+                Some(MapEntry::Leaf(_, s)) => {
+                    !space.contains_loc(s) || self.diverging_level(k, s, space).is_some()
+                }
+                _ => false
+            })
+            .map(|(spatial_key, _)| spatial_key)
+            .collect()
+    }
+
+    /// Returns the first level at which `loc` falls in a different child
+    /// than the node at `key`, or none if it lies within that node
+    fn diverging_level<B>(&self, key: K, loc: &S, space: B) -> Option<u32>
+        where B: Partition<S, Child = K::Child> {
+        let mut bounds = space;
+        for level in 1..=key.level() {
+            let child = bounds.find_child(loc);
+            if child != key.child_at_level(level) {
+                return Some(level);
+            }
+            bounds = bounds.child_bounds(child);
+        }
+        None
+    }
+
+    /// Stores the element below the branch at `start`, splitting leaves as
+    /// needed, and points `spatial_key` at the key it ends up with. The
+    /// element is handed back along with the error if it can not be stored
+    pub(crate) fn place<B>(&mut self, spatial_key: SpatialKey, loc: S, start: K, space: B)
+        -> std::result::Result<(), (SpatialError, S)>
+        where B: Partition<S, Child = K::Child> {
+        match self.free_key(&loc, start, space) {
+            Ok(key) => self.insert_leaf(spatial_key, key, loc),
+            Err(e) => Err((e, loc))
+        }
+    }
+
+    /// Returns the free key below the branch at `start` that an element
+    /// at `loc` is stored at, moving a leaf it collides with further down
+    fn free_key<B>(&mut self, loc: &S, start: K, space: B) -> Result<K>
+        where B: Partition<S, Child = K::Child> {
+
+        let mut ret = start;
+        let mut bounds = space;
+        for level in 1..=start.level() {
+            bounds = bounds.child_bounds(start.child_at_level(level));
+        }
+
+        loop {
+            if ret.level() == K::MAX_LEVEL {
+                return self.free_overflow(ret);
+            }
+            let part = bounds.find_child(loc);
+            bounds = bounds.child_bounds(part);
+            let mut child = ret.child(part)?;
+            match self.spatial_map.get(&child) {
+                // hit a branch; keep going
+                Some(MapEntry::Branch) => {
+                    trace!(key = %child, level = child.level(), "descending into branch");
+                    ret = child
+                },
+                // solve collision by moving both colliding keys
+                // down the tree
+                Some(MapEntry::Leaf(..)) => {
+                    debug!(key = %child, level = child.level(), "collision with existing leaf");
+                    let (invalid_key, loc2) = match self.spatial_map.remove(&child) {
+                        Some(MapEntry::Leaf(invalid_key, loc2)) => (invalid_key, loc2),
+                        _ => unreachable!()
+                    };
+                    loop {
+                        // out of resolution; both share the deepest node
+                        if child.level() == K::MAX_LEVEL {
+                            debug!(key = %child, "collision at maximum depth, using overflow");
+                            self.insert_leaf(invalid_key, child, loc2).map_err(|(e, _)| e)?;
+                            return self.free_overflow(child);
+                        }
+
+                        self.spatial_map.insert(child, MapEntry::Branch);
+
+                        let part1 = bounds.find_child(loc);
+                        let part2 = bounds.find_child(&loc2);
+
+                        // still collide
+                        if part1 == part2 {
+                            bounds = bounds.child_bounds(part1);
+                            child = child.child(part1)?;
+                        }
+                        // seperated after latest subdivision
+                        else {
+                            let child1 = child.child(part1)?;
+                            let child2 = child.child(part2)?;
+
+                            debug!(
+                                key = %child1,
+                                moved_key = %child2,
+                                collision_depth = child1.level(),
+                                "collision resolved"
+                            );
+
+                            self.insert_leaf(invalid_key, child2, loc2).map_err(|(e, _)| e)?;
+                            return Ok(child1);
+                        }
+                    }
+                }
+                // empty value, take coordinates for this spatial
+                None => {
+                    trace!(key = %child, level = child.level(), "found free leaf");
+                    return Ok(child);
+                }
+            };
+        };
+    }
+
+    /// Returns the first free overflow key of a node at the deepest level
+    fn free_overflow(&self, key: K) -> Result<K> {
+        let mut key = key;
+        while self.spatial_map.contains_key(&key) {
+            key.increment_overflow()?;
+        }
+        trace!(key = %key, overflow = key.overflow(), "found free overflow leaf");
+        Ok(key)
+    }
+
+    /// Stores the element in a new leaf at `key`, or hands it back with
+    /// a `DuplicateKey` error if the key is already taken
+    pub(crate) fn insert_leaf(&mut self, spatial_key: SpatialKey, key: K, loc: S)
+        -> std::result::Result<(), (SpatialError, S)> {
+        if self.spatial_map.contains_key(&key) {
+            return Err((SpatialError::DuplicateKey, loc));
+        }
+        *self.key_map.get_mut(spatial_key).unwrap() = key;
+        self.spatial_map.insert(key, MapEntry::Leaf(spatial_key, loc));
+        Ok(())
+    }
+
+    /// Removes the leaf at `k` and restores the shape of the tree around it.
+    /// The persistent key of the removed element is left for the caller
+    pub(crate) fn detach(&mut self, k: K) -> Option<(SpatialKey, S)> {
+
+        // nodes at the deepest level may be shared through their overflow
+        // keys, which are kept contiguous by moving the last one into the gap
+        let last = self.bucket_keys(k.without_overflow()).last().unwrap_or(k);
+        let shared = last.overflow().is_some();
+
+        let (spatial_key, s) = match self.spatial_map.remove(&k) {
+            Some(MapEntry::Leaf(spatial_key, s)) => (spatial_key, s),
+            Some(MapEntry::Branch) => {
+                self.spatial_map.insert(k, MapEntry::Branch);
+                return None;
+            }
+            None => return None
+        };
+
+        if shared {
+            if last != k {
+                let moved = self.spatial_map.remove(&last).unwrap();
+                if let MapEntry::Leaf(moved_key, _) = moved {
+                    *self.key_map.get_mut(moved_key).unwrap() = k;
+                }
+                self.spatial_map.insert(k, moved);
+            }
+            // the node is still shared by other elements
+            if last.overflow() != Some(1) {
+                return Some((spatial_key, s));
+            }
+        }
+
+        // walk up the tree while the branch above holds at most one element:
+        //  0 elements: the branch is dead and is removed
+        //  1 element: it can only be a leaf directly below the branch, as
+        // deeper branches always hold at least two elements. the leaf
+        // replaces the branch so that spatial complexity is recovered
+        //  2 or more elements: the remaining leaves need their current
+        // levels to stay separated, so nothing changes further up
+        let mut node = k.without_overflow();
+        while node.level() > 1 {
+            let parent = node.parent().unwrap();
+            match self.count_elements(parent, 2) {
+                0 => {
+                    self.spatial_map.remove(&parent);
+                }
+                1 => {
+                    let lone = K::CHILDREN.iter()
+                        .filter_map(|&child| parent.child(child).ok())
+                        .find(|child| self.spatial_map.contains_key(child))
+                        .unwrap();
+                    match self.spatial_map.remove(&lone) {
+                        Some(MapEntry::Leaf(moved_key, moved)) => {
+                            *self.key_map.get_mut(moved_key).unwrap() = parent;
+                            self.spatial_map.insert(parent, MapEntry::Leaf(moved_key, moved));
+                        }
+                        Some(entry) => {
+                            self.spatial_map.insert(lone, entry);
+                            break;
+                        }
+                        None => unreachable!()
+                    }
+                }
+                _ => break
+            }
+            node = parent;
+        }
+        Some((spatial_key, s))
+    }
+
+    /// Returns the number of elements at or below `key`, counting
+    /// no further than `limit`
+    fn count_elements(&self, key: K, limit: u32) -> u32 {
+        match self.spatial_map.get(&key) {
+            Some(MapEntry::Leaf(..)) => {
+                self.bucket_keys(key).take(limit as usize).count() as u32
+            }
+            Some(MapEntry::Branch) => {
+                let mut ret = 0;
+                for &child in K::CHILDREN {
+                    if let Ok(child) = key.child(child) {
+                        ret += self.count_elements(child, limit - ret);
+                        if ret >= limit {
+                            break;
+                        }
+                    }
+                }
+                ret
+            }
+            None => 0
+        }
+    }
+
+    /// Returns the keys of every leaf adjacent to the leaf at `key`
+    pub(crate) fn neighboring_keys(&self, key: SpatialKey) -> Vec<K> {
+        let key = match self.key_map.get(key) {
+            Some(key) => *key,
+            None => return vec![]
+        };
+
+        let mut ret = Vec::new();
+        for same_size_key in key.neighbors() {
+            match self.spatial_map.get(&same_size_key) {
+                // neighbor is subdivided further; collect every leaf
+                // along the side facing this key
+                Some(MapEntry::Branch) => {
+                    let mut stack = vec![same_size_key];
+                    while let Some(branch) = stack.pop() {
+                        for &child in K::CHILDREN {
+                            let child = match branch.child(child) {
+                                Ok(child) => child,
+                                Err(_) => continue
+                            };
+                            if !child.is_adjacent(&key) {continue;}
+                            match self.spatial_map.get(&child) {
+                                Some(MapEntry::Branch) => stack.push(child),
+                                Some(MapEntry::Leaf(..)) => ret.push(child),
+                                None => ()
+                            }
+                        }
+                    }
+                }
+                Some(MapEntry::Leaf(..)) => {
+                    ret.push(same_size_key);
+                }
+                // neighbor may be covered by a larger leaf further up the tree
+                None => {
+                    let mut parent_key = same_size_key.parent();
+                    while let Some(k) = parent_key {
+                        if k.level() == 0 {break;}
+                        match self.spatial_map.get(&k) {
+                            Some(MapEntry::Leaf(..)) => {
+                                ret.push(k);
+                                break;
+                            }
+                            Some(MapEntry::Branch) => break,
+                            None => parent_key = k.parent()
+                        }
+                    }
+                }
+            }
+        }
+        // elements sharing a node through overflow keys are neighbors
+        // of each other and of everything next to that node
+        let mut ret: Vec<K> = ret.into_iter()
+            .chain(std::iter::once(key.without_overflow()))
+            .flat_map(|k| self.bucket_keys(k))
+            .filter(|k| *k != key)
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// Returns mutable references to the leaves at each of the given keys,
+    /// in the same order. Keys given more than once are only returned once
+    pub(crate) fn leaves_mut(&mut self, keys: &[K]) -> Vec<&mut S> {
+        let mut seen = HashSet::with_capacity(keys.len());
+        let mut ret = Vec::with_capacity(keys.len());
+        for key in keys {
+            if !seen.insert(*key) {continue;}
+            if let Some(MapEntry::Leaf(_, s)) = self.spatial_map.get_mut(key) {
+                // SAFETY: the keys are distinct, so each reference points to a
+                // distinct entry of the map and none of them alias. The map is
+                // borrowed mutably while the references are alive, so it is
+                // not modified or rehashed in the meantime
+                ret.push(unsafe { &mut *(s as *mut S) });
+            }
+        }
+        ret
+    }
+}
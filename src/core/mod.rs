@@ -1,9 +1,23 @@
+mod area_node;
 mod bounds;
+mod bounds3d;
+mod candidate;
 mod error;
+mod index;
+mod linear_map;
+mod octant;
 mod quadrant;
+mod scalar;
 mod types;
 
 pub use error::{SpatialError, Result};
+pub use index::{SpatialIndex, IndexScalar};
 pub use quadrant::{Quadrant, QUADRANTS};
+pub use octant::{Octant, OCTANTS};
+pub use scalar::Scalar;
 pub use types::*;
-pub use bounds::Bounds;
\ No newline at end of file
+pub use bounds::Bounds;
+pub use bounds3d::Bounds3D;
+pub(crate) use area_node::AreaNode;
+pub(crate) use candidate::{Candidate, CandidateEntry};
+pub(crate) use linear_map::{LinearKey, LinearMap, MapEntry};
\ No newline at end of file
@@ -0,0 +1,20 @@
+/// Every octant, in the order of their discriminants
+pub const OCTANTS: [Octant; 8] = [
+    Octant::TLN, Octant::TRN, Octant::BLN, Octant::BRN,
+    Octant::TLF, Octant::TRF, Octant::BLF, Octant::BRF,
+];
+
+/// One of the eight children of a cube. The first two letters name the
+/// quadrant in the xy plane as in [`Quadrant`](crate::core::Quadrant),
+/// the last one the near (low z) or far (high z) half
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Octant {
+    TLN,
+    TRN,
+    BLN,
+    BRN,
+    TLF,
+    TRF,
+    BLF,
+    BRF,
+}
@@ -8,6 +8,19 @@ pub enum Quadrant {
     BR,
 }
 
+impl Quadrant {
+    /// Returns the position of the quadrant within [`QUADRANTS`],
+    /// which lists the quadrants in Morton order
+    pub fn index(self) -> usize {
+        match self {
+            Quadrant::BL => 0,
+            Quadrant::BR => 1,
+            Quadrant::TL => 2,
+            Quadrant::TR => 3,
+        }
+    }
+}
+
 //impl Quadrant {
 //    /// Returns iterator of quadrants that is not self
 //    pub fn all_others(&self) -> Vec<Quadrant> {
@@ -15,20 +28,3 @@ pub enum Quadrant {
 //    }
 //}
 
-pub enum QuadtreeError {
-    BoundsError,
-    DepthError,
-}
-
-use std::fmt;
-use std::iter::Filter;
-
-impl fmt::Display for QuadtreeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            QuadtreeError::BoundsError => write!(f, "Point out of bounds"),
-            QuadtreeError::DepthError => write!(f, "Maximum tree recursion depth reached"),
-        }
-
-    }
-}
\ No newline at end of file
@@ -0,0 +1,108 @@
+use std::fmt::Debug;
+use std::ops::{Add, Sub, Mul, Div};
+
+/// Numeric type of coordinates, implemented for `f32`, `f64`,
+/// `i32` and `i64`
+pub trait Scalar:
+    Copy + PartialOrd + Debug + Default +
+    Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> {
+    const ZERO: Self;
+    const ONE: Self;
+    /// Largest value, infinity for floating point types
+    const MAX: Self;
+
+    /// Type of squared distances. Integer types use one wide enough
+    /// to hold them exactly, so they are compared without rounding
+    type Squared: Copy + PartialOrd + Debug;
+
+    /// Square root, rounded towards zero for integer types
+    fn sqrt(self) -> Self;
+    fn to_f64(self) -> f64;
+    fn from_f64(value: f64) -> Self;
+
+    /// Returns the squared distance between the points with coordinates `a` and `b`
+    fn squared_distance(a: &[Self], b: &[Self]) -> Self::Squared;
+    /// Returns the distance of a squared distance, rounded towards zero
+    /// for integer types
+    fn from_squared(squared: Self::Squared) -> Self;
+
+    /// Returns true if `squared` is the squared distance of a
+    /// point that is at most `radius` away
+    fn within_radius(squared: Self::Squared, radius: Self) -> bool {
+        radius >= Self::ZERO && squared <= Self::squared_distance(&[radius], &[Self::ZERO])
+    }
+
+    fn partial_min(self, other: Self) -> Self {
+        if other < self { other } else { self }
+    }
+
+    fn partial_max(self, other: Self) -> Self {
+        if other > self { other } else { self }
+    }
+
+    fn half(self) -> Self {
+        self / (Self::ONE + Self::ONE)
+    }
+}
+
+macro_rules! impl_float_scalar {
+    ($($t:ty),*) => {$(
+        impl Scalar for $t {
+            const ZERO: Self = 0.;
+            const ONE: Self = 1.;
+            const MAX: Self = <$t>::INFINITY;
+
+            type Squared = $t;
+
+            fn sqrt(self) -> Self { <$t>::sqrt(self) }
+            fn to_f64(self) -> f64 { self as f64 }
+            fn from_f64(value: f64) -> Self { value as $t }
+
+            fn squared_distance(a: &[Self], b: &[Self]) -> Self {
+                a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+            }
+
+            fn from_squared(squared: Self) -> Self { squared.sqrt() }
+        }
+    )*}
+}
+
+macro_rules! impl_int_scalar {
+    ($($t:ty),*) => {$(
+        impl Scalar for $t {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+            const MAX: Self = <$t>::MAX;
+
+            // the difference of two `i64` values fits into a `u64`,
+            // so its square always fits into a `u128`
+            type Squared = u128;
+
+            fn sqrt(self) -> Self { (self as f64).sqrt() as $t }
+            fn to_f64(self) -> f64 { self as f64 }
+            fn from_f64(value: f64) -> Self { value as $t }
+
+            fn squared_distance(a: &[Self], b: &[Self]) -> u128 {
+                a.iter().zip(b).fold(0, |sum, (&a, &b)| {
+                    let d = (a as i128 - b as i128).unsigned_abs();
+                    sum.saturating_add(d * d)
+                })
+            }
+
+            fn from_squared(squared: u128) -> Self {
+                // the estimate is off by a few units at most for large values
+                let mut root = (squared as f64).sqrt() as u128;
+                while root.checked_mul(root).map_or(true, |sq| sq > squared) {
+                    root -= 1;
+                }
+                while (root + 1).checked_mul(root + 1).map_or(false, |sq| sq <= squared) {
+                    root += 1;
+                }
+                root.min(<$t>::MAX as u128) as $t
+            }
+        }
+    )*}
+}
+
+impl_float_scalar!(f32, f64);
+impl_int_scalar!(i32, i64);
@@ -1,44 +1,51 @@
-#[allow(dead_code)]
-#[allow(non_snake_case)]
-
 use std::fmt;
 use std::f32;
 use std::fmt::Debug;
+use crate::core::{Bounds, Scalar};
+
 
+new_key_type!{
+    /// Persistent handle to an element stored in one of the
+    /// spatial containers, returned on insertion
+    pub struct SpatialKey;
+}
 
 pub trait Spatial2D {
-    fn x(&self) -> f32;
-    fn y(&self) -> f32;
-    fn pos(&self) -> (f32, f32) {
+    type Scalar: Scalar;
+    fn x(&self) -> Self::Scalar;
+    fn y(&self) -> Self::Scalar;
+    fn pos(&self) -> (Self::Scalar, Self::Scalar) {
         (self.x(), self.y())
     }
-    fn distance_to(&self, other: &dyn Spatial2D) -> f32 {
-        let x = self.x() - other.x();
-        let y = self.y() - other.y();
-        ((x*x) + (y*y)).sqrt()
+    fn distance_to(&self, other: &dyn Spatial2D<Scalar = Self::Scalar>) -> Self::Scalar {
+        Self::Scalar::from_squared(self.squared_distance_to(other))
     }
+    /// Squared distance to `other`, which is exact for integer types
+    fn squared_distance_to(&self, other: &dyn Spatial2D<Scalar = Self::Scalar>) -> <Self::Scalar as Scalar>::Squared {
+        Self::Scalar::squared_distance(&[self.x(), self.y()], &[other.x(), other.y()])
+    }
+}
+
+/// Object covering an area, indexed by the bounds enclosing it
+pub trait SpatialBounds2D {
+    type Scalar: Scalar;
+    fn bounds(&self) -> Bounds<Self::Scalar>;
 }
 
 pub trait Spatial3D {
-    fn x(&self) -> f32;
-    fn y(&self) -> f32;
-    fn z(&self) -> f32;
-    fn loc(&self) -> (f32, f32, f32) {
+    type Scalar: Scalar;
+    fn x(&self) -> Self::Scalar;
+    fn y(&self) -> Self::Scalar;
+    fn z(&self) -> Self::Scalar;
+    fn loc(&self) -> (Self::Scalar, Self::Scalar, Self::Scalar) {
         (self.x(), self.y(), self.z())
     }
-    fn distance_to(&self, other: &dyn Spatial3D) -> f32 {
-        let x = self.x() - other.x();
-        let y = self.y() - other.y();
-        let z = self.z() - other.z();
-        ((x*x) + (y*y) + (z*z)).sqrt()
+    fn distance_to(&self, other: &dyn Spatial3D<Scalar = Self::Scalar>) -> Self::Scalar {
+        Self::Scalar::from_squared(self.squared_distance_to(other))
     }
-}
-
-pub struct SpatialError;
-
-impl fmt::Display for SpatialError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Item inserted is outside tree bounds")
+    /// Squared distance to `other`, which is exact for integer types
+    fn squared_distance_to(&self, other: &dyn Spatial3D<Scalar = Self::Scalar>) -> <Self::Scalar as Scalar>::Squared {
+        Self::Scalar::squared_distance(&[self.x(), self.y(), self.z()], &[other.x(), other.y(), other.z()])
     }
 }
 
@@ -55,38 +62,55 @@ impl Point2D {
 }
 
 impl Spatial2D for Point2D {
+    type Scalar = f32;
     fn x(&self) -> f32 {self.x}
     fn y(&self) -> f32 {self.y}
 }
 
-impl Spatial2D for [f32; 2] {
-    fn x(&self) -> f32 {self[0]}
-    fn y(&self) -> f32 {self[1]}
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Point3D {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Point3D {
+    pub fn new(x: f32, y: f32, z: f32) -> Point3D {
+        Point3D { x, y, z }
+    }
+}
+
+impl Spatial3D for Point3D {
+    type Scalar = f32;
+    fn x(&self) -> f32 {self.x}
+    fn y(&self) -> f32 {self.y}
+    fn z(&self) -> f32 {self.z}
 }
 
-impl Spatial2D for [f64; 2] {
-    fn x(&self) -> f32 {self[0] as f32}
-    fn y(&self) -> f32 {self[1] as f32}
+impl<S> Spatial2D for [S; 2]
+    where S: Scalar {
+    type Scalar = S;
+    fn x(&self) -> S {self[0]}
+    fn y(&self) -> S {self[1]}
 }
 
-impl Spatial3D for [f32; 3] {
-    fn x(&self) -> f32 {self[0]}
-    fn y(&self) -> f32 {self[1]}
-    fn z(&self) -> f32 {self[2]}
+impl<S> SpatialBounds2D for Bounds<S>
+    where S: Scalar {
+    type Scalar = S;
+    fn bounds(&self) -> Bounds<S> {*self}
 }
 
-impl Spatial3D for [f64; 3] {
-    fn x(&self) -> f32 {self[0] as f32}
-    fn y(&self) -> f32 {self[1] as f32}
-    fn z(&self) -> f32 {self[2] as f32}
+impl<S> Spatial3D for [S; 3]
+    where S: Scalar {
+    type Scalar = S;
+    fn x(&self) -> S {self[0]}
+    fn y(&self) -> S {self[1]}
+    fn z(&self) -> S {self[2]}
 }
 
-impl Debug for Spatial2D {
+impl<S> Debug for dyn Spatial2D<Scalar = S>
+    where S: Scalar {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "x: {:?}, y: {:?}", self.x(), self.y())
     }
 }
-
-
-
-
@@ -1,6 +1,14 @@
 #[macro_use]
 extern crate slotmap;
 
+#[macro_use]
+mod macros;
+
 pub mod core;
+pub mod linear_octree;
 pub mod linear_quadtree;
-pub mod pointer_quadtree;
\ No newline at end of file
+pub mod loose_quadtree;
+pub mod pointer_octree;
+pub mod pointer_quadtree;
+pub mod region_quadtree;
+pub mod spatial_map;
\ No newline at end of file
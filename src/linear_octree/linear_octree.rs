@@ -0,0 +1,392 @@
+
+
+use crate::linear_octree::Key;
+use crate::core::{Spatial3D, SpatialKey, Bounds3D, Scalar, OCTANTS, Candidate, CandidateEntry, LinearMap, MapEntry, Result, SpatialError};
+use std::collections::BinaryHeap;
+
+/// Octree stored as a hash map from the locational keys of its nodes,
+/// the counterpart of [`LinearQuadtree`](crate::linear_quadtree::LinearQuadtree)
+#[derive(Default)]
+pub struct LinearOctree<S>
+    where S: Spatial3D {
+    map: LinearMap<Key, S>,
+    space_boundary: Bounds3D<S::Scalar>,
+}
+
+impl<S> LinearOctree<S>
+    where S: Spatial3D {
+    pub fn new(space_boundary: Bounds3D<S::Scalar>) -> Self {
+        Self {
+            map: LinearMap::default(),
+            space_boundary
+        }
+    }
+
+    /// Inserts Spatial3D into octree and returns
+    /// a persistent key that indexes it.
+    ///
+    /// Elements that still share a node at the deepest level of
+    /// the tree are told apart by the overflow bits of their keys.
+    /// Returns a `KeyOverflow` error once all of them are in use
+    pub fn insert(&mut self, loc: S) -> Result<SpatialKey> {
+        if !self.space_boundary.contains_point(&loc) {
+            return Err(SpatialError::out_of_bounds_3d(loc.loc(), self.space_boundary));
+        }
+        self.map.insert(loc, self.space_boundary)
+    }
+
+    pub fn remove(&mut self, key: SpatialKey) -> Option<S> {
+        self.map.remove(key)
+    }
+
+    /// Replaces the element at `key` with `loc` and returns the old element,
+    /// or none if there is no element at `key`.
+    ///
+    /// If `loc` lies in the same node as the old element only the stored
+    /// value changes. Otherwise the element is removed from its node and
+    /// placed again starting from the deepest branch that holds both the old
+    /// and new location. The key stays valid either way
+    pub fn update(&mut self, key: SpatialKey, loc: S) -> Result<Option<S>> {
+        if !self.space_boundary.contains_point(&loc) {
+            return Err(SpatialError::out_of_bounds_3d(loc.loc(), self.space_boundary));
+        }
+        self.map.update(key, loc, self.space_boundary)
+    }
+
+    /// Calls `f` on every element and re-indexes the ones that moved.
+    ///
+    /// Elements that `f` moves out of the space boundary are removed
+    /// and returned along with their keys
+    pub fn move_all<F>(&mut self, mut f: F) -> Vec<(SpatialKey, S)>
+        where F: FnMut(SpatialKey, &mut S) {
+        for entry in self.map.spatial_map.values_mut() {
+            if let MapEntry::Leaf(key, s) = entry {
+                f(*key, s);
+            }
+        }
+        self.map.reindex(self.space_boundary)
+    }
+
+    /// Returns every element whose node shares a face, edge or corner with
+    /// the node of the element at `key`. This includes smaller nodes inside
+    /// a larger neighboring branch and larger nodes further up the tree
+    pub fn neighbors(&self, key: SpatialKey) -> Vec<&S> {
+        self.map.neighboring_keys(key)
+            .into_iter()
+            .filter_map(|k| self.map.leaf(k))
+            .collect()
+    }
+
+    /// Mutable version of [`LinearOctree::neighbors`]. Moving the returned
+    /// elements does not update their position within the tree
+    pub fn neighbors_mut(&mut self, key: SpatialKey) -> Vec<&mut S> {
+        let keys = self.map.neighboring_keys(key);
+        self.map.leaves_mut(&keys)
+    }
+
+    /// Returns every element within `radius` of the element at `key`,
+    /// not including the element itself
+    pub fn neighbors_within(&self, key: SpatialKey, radius: S::Scalar) -> Vec<&S> {
+        self.keys_within(key, radius)
+            .into_iter()
+            .filter_map(|k| self.map.leaf(k))
+            .collect()
+    }
+
+    /// Mutable version of [`LinearOctree::neighbors_within`]. Moving the
+    /// returned elements does not update their position within the tree
+    pub fn neighbors_within_mut(&mut self, key: SpatialKey, radius: S::Scalar) -> Vec<&mut S> {
+        let keys = self.keys_within(key, radius);
+        self.map.leaves_mut(&keys)
+    }
+
+    /// Returns an iterator over every element inside `query`, visiting
+    /// only the keys whose region intersects it
+    pub fn query_bounds(&self, query: &Bounds3D<S::Scalar>) -> QueryBounds<'_, S> {
+        let mut iter = QueryBounds {
+            tree: self,
+            query: *query,
+            stack: vec![]
+        };
+        if self.space_boundary.intersects(*query) {
+            iter.push_children(Key::default(), self.space_boundary);
+        }
+        iter
+    }
+
+    /// Returns up to `k` elements closest to `p` along with their distances,
+    /// sorted from nearest to farthest. If `max_dist` is given, elements
+    /// farther away than it are not returned
+    pub fn k_nearest(&self, p: &dyn Spatial3D<Scalar = S::Scalar>, k: usize, max_dist: Option<S::Scalar>) -> Vec<(&S, S::Scalar)> {
+        self.nearest(p)
+            .take_while(|&(_, _, squared)| match max_dist {
+                Some(max) => S::Scalar::within_radius(squared, max),
+                None => true
+            })
+            .take(k)
+            .map(|(_, s, squared)| (s, S::Scalar::from_squared(squared)))
+            .collect()
+    }
+
+    /// Returns an iterator over elements and their keys in order of increasing distance to `p`
+    fn nearest(&self, p: &dyn Spatial3D<Scalar = S::Scalar>) -> Nearest<'_, S> {
+        let point = [p.x(), p.y(), p.z()];
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate::node(
+            self.space_boundary.squared_distance_to_point(&point),
+            (Key::default(), self.space_boundary)
+        ));
+        Nearest {
+            tree: self,
+            point,
+            queue
+        }
+    }
+
+    pub fn values(&self) -> Vec<&S> {
+        self.map.spatial_map.values()
+            .filter_map(|entry| match entry {
+                MapEntry::Leaf(_, s) => Some(s),
+                MapEntry::Branch => None
+            })
+            .collect()
+    }
+
+    /// Returns all bounds that make up the hierarchy of the octree
+    pub fn bounds(&self) -> Vec<Bounds3D<S::Scalar>> {
+        let mut ret = Vec::new();
+        for key in self.map.spatial_map.keys() {
+            ret.push(key.to_bounds(&self.space_boundary));
+        }
+        ret
+    }
+
+    /// Returns the bounds of every node on the tree that contains
+    /// a Spatial element, skipping branches
+    pub fn bounds_no_branch(&self) -> Vec<Bounds3D<S::Scalar>> {
+        let mut ret = Vec::new();
+        for (_, key) in &self.map.key_map {
+            ret.push(key.to_bounds(&self.space_boundary));
+        }
+        ret
+    }
+
+    /// Returns the keys of every leaf within `radius` of the leaf at `key`,
+    /// not including `key` itself
+    fn keys_within(&self, key: SpatialKey, radius: S::Scalar) -> Vec<Key> {
+        let key = match self.map.key_map.get(key) {
+            Some(key) => *key,
+            None => return vec![]
+        };
+        let center = match self.map.spatial_map.get(&key) {
+            Some(MapEntry::Leaf(_, s)) => [s.x(), s.y(), s.z()],
+            _ => return vec![]
+        };
+        self.nearest(&center)
+            .take_while(|&(_, _, squared)| S::Scalar::within_radius(squared, radius))
+            .map(|(k, _, _)| k)
+            .filter(|k| *k != key)
+            .collect()
+    }
+}
+
+/// Iterator over the elements inside a query region, created by
+/// [`LinearOctree::query_bounds`]
+pub struct QueryBounds<'a, S>
+    where S: Spatial3D {
+    tree: &'a LinearOctree<S>,
+    query: Bounds3D<S::Scalar>,
+    stack: Vec<(Key, Bounds3D<S::Scalar>)>,
+}
+
+impl<'a, S> QueryBounds<'a, S>
+    where S: Spatial3D {
+    fn push_children(&mut self, key: Key, bounds: Bounds3D<S::Scalar>) {
+        for &octant in OCTANTS.iter() {
+            if let Ok(child) = key.child(octant) {
+                let child_bounds = bounds.sub_bound(octant);
+                if child_bounds.intersects(self.query) {
+                    self.stack.push((child, child_bounds));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S> Iterator for QueryBounds<'a, S>
+    where S: Spatial3D {
+    type Item = &'a S;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, bounds)) = self.stack.pop() {
+            match self.tree.map.spatial_map.get(&key) {
+                Some(MapEntry::Branch) => self.push_children(key, bounds),
+                Some(MapEntry::Leaf(_, s)) => {
+                    if key.overflow().is_none() {
+                        for overflow_key in self.tree.map.bucket_keys(key).skip(1) {
+                            self.stack.push((overflow_key, bounds));
+                        }
+                    }
+                    if self.query.contains_point(s) {
+                        return Some(s);
+                    }
+                }
+                None => ()
+            }
+        }
+        None
+    }
+}
+
+type NearestCandidate<'a, S> = Candidate<
+    (Key, Bounds3D<<S as Spatial3D>::Scalar>),
+    (Key, &'a S),
+    <<S as Spatial3D>::Scalar as Scalar>::Squared
+>;
+
+/// Best-first traversal yielding elements and their squared distances to a point,
+/// nearest first. Branches are expanded lazily in order of their minimum
+/// possible distance
+struct Nearest<'a, S>
+    where S: Spatial3D {
+    tree: &'a LinearOctree<S>,
+    point: [S::Scalar; 3],
+    queue: BinaryHeap<NearestCandidate<'a, S>>,
+}
+
+impl<'a, S> Iterator for Nearest<'a, S>
+    where S: Spatial3D {
+    type Item = (Key, &'a S, <S::Scalar as Scalar>::Squared);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Candidate { distance, entry }) = self.queue.pop() {
+            match entry {
+                CandidateEntry::Item((key, s)) => return Some((key, s, distance)),
+                CandidateEntry::Node((key, bounds)) => {
+                    for &octant in OCTANTS.iter() {
+                        let child = match key.child(octant) {
+                            Ok(child) => child,
+                            Err(_) => continue
+                        };
+                        let child_bounds = bounds.sub_bound(octant);
+                        match self.tree.map.spatial_map.get(&child) {
+                            Some(MapEntry::Branch) => self.queue.push(Candidate::node(
+                                child_bounds.squared_distance_to_point(&self.point),
+                                (child, child_bounds)
+                            )),
+                            Some(MapEntry::Leaf(..)) => {
+                                for leaf in self.tree.map.bucket_keys(child) {
+                                    if let Some(MapEntry::Leaf(_, s)) = self.tree.map.spatial_map.get(&leaf) {
+                                        self.queue.push(Candidate::item(
+                                            s.squared_distance_to(&self.point),
+                                            (leaf, s)
+                                        ));
+                                    }
+                                }
+                            }
+                            None => ()
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LinearOctree;
+    use crate::core::{Bounds3D, Point3D, SpatialError};
+
+    #[test]
+    fn test_query_bounds() {
+        let mut tree = LinearOctree::new(Bounds3D::new(0., 100., 0., 100., 0., 100.));
+        for i in 0..5 {
+            for j in 0..5 {
+                for k in 0..5 {
+                    tree.insert(Point3D::new(i as f32 * 20. + 5., j as f32 * 20. + 5., k as f32 * 20. + 5.)).unwrap();
+                }
+            }
+        }
+
+        let mut found: Vec<Point3D> = tree.query_bounds(&Bounds3D::new(20., 50., 60., 70., 0., 30.)).cloned().collect();
+        found.sort_by(|a, b| (a.x, a.y, a.z).partial_cmp(&(b.x, b.y, b.z)).unwrap());
+        assert_eq!(found, vec![
+            Point3D::new(25., 65., 5.), Point3D::new(25., 65., 25.),
+            Point3D::new(45., 65., 5.), Point3D::new(45., 65., 25.),
+        ]);
+
+        assert_eq!(tree.query_bounds(&Bounds3D::new(0., 100., 0., 100., 0., 100.)).count(), 125);
+        assert_eq!(tree.query_bounds(&Bounds3D::new(1., 4., 1., 4., 1., 4.)).count(), 0);
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let mut tree = LinearOctree::new(Bounds3D::new(0., 100., 0., 100., 0., 100.));
+
+        // one point in three of the top level octants, and two in the
+        // far corner so that it holds smaller leaves
+        let origin = tree.insert(Point3D::new(20., 20., 20.)).unwrap();
+        tree.insert(Point3D::new(80., 20., 20.)).unwrap();
+        tree.insert(Point3D::new(20., 80., 80.)).unwrap();
+        let near = tree.insert(Point3D::new(55., 55., 55.)).unwrap();
+        tree.insert(Point3D::new(70., 70., 70.)).unwrap();
+
+        let mut found: Vec<Point3D> = tree.neighbors(origin).into_iter().cloned().collect();
+        found.sort_by(|a, b| (a.x, a.y, a.z).partial_cmp(&(b.x, b.y, b.z)).unwrap());
+        assert_eq!(found, vec![
+            Point3D::new(20., 80., 80.),
+            Point3D::new(55., 55., 55.),
+            Point3D::new(80., 20., 20.),
+        ]);
+
+        // the larger leaf at the origin is found from a small leaf
+        // touching it at a single corner
+        let mut found: Vec<Point3D> = tree.neighbors(near).into_iter().cloned().collect();
+        found.sort_by(|a, b| (a.x, a.y, a.z).partial_cmp(&(b.x, b.y, b.z)).unwrap());
+        assert_eq!(found, vec![
+            Point3D::new(20., 20., 20.),
+            Point3D::new(20., 80., 80.),
+            Point3D::new(70., 70., 70.),
+            Point3D::new(80., 20., 20.),
+        ]);
+
+        let found = tree.neighbors_within(near, 30.);
+        assert_eq!(found, vec![&Point3D::new(70., 70., 70.)]);
+    }
+
+    #[test]
+    fn test_update_remove() {
+        let mut tree = LinearOctree::new(Bounds3D::new(0., 100., 0., 100., 0., 100.));
+        let a = tree.insert(Point3D::new(10., 10., 10.)).unwrap();
+        let b = tree.insert(Point3D::new(12., 12., 12.)).unwrap();
+        let c = tree.insert(Point3D::new(90., 90., 90.)).unwrap();
+        assert_eq!(tree.insert(Point3D::new(10., 10., 110.)), Err(SpatialError::OutOfBounds {
+            point: [10., 10., 110.],
+            bounds: [(0., 100.), (0., 100.), (0., 100.)],
+        }));
+        // the leaf of `c` can not take another element
+        let taken = tree.map.key_map[c];
+        let stored = tree.map.insert_leaf(a, taken, Point3D::new(90., 90., 90.));
+        assert_eq!(stored, Err((SpatialError::DuplicateKey, Point3D::new(90., 90., 90.))));
+
+        assert_eq!(tree.update(a, Point3D::new(60., 10., 10.)).unwrap(), Some(Point3D::new(10., 10., 10.)));
+        // the branch that separated `a` from `b` collapsed
+        assert_eq!(tree.bounds().len(), 3);
+
+        let found = tree.k_nearest(&Point3D::new(61., 10., 10.), 2, None);
+        assert_eq!(found[0], (&Point3D::new(60., 10., 10.), 1.));
+        assert_eq!(found[1].0, &Point3D::new(12., 12., 12.));
+
+        let outside = tree.move_all(|_, p| p.x += 30.);
+        assert_eq!(outside, vec![(c, Point3D::new(120., 90., 90.))]);
+        assert_eq!(tree.k_nearest(&Point3D::new(40., 10., 10.), 1, None)[0].0, &Point3D::new(42., 12., 12.));
+
+        assert_eq!(tree.remove(b), Some(Point3D::new(42., 12., 12.)));
+        assert_eq!(tree.remove(b), None);
+        assert_eq!(tree.remove(c), None);
+        assert!(tree.remove(a).is_some());
+        assert!(tree.bounds().is_empty());
+    }
+}
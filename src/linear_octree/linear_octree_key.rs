@@ -0,0 +1,434 @@
+use crate::core::{Octant, OCTANTS, Bounds3D, Scalar, Result, SpatialError, LinearKey};
+use std::fmt::Formatter;
+
+mod consts {
+    /// maximum depth of the tree using these nodes
+    pub const RESOLUTION: u32 = 8;
+    /// constant for neighbor calculation
+    pub const T_X: u32 = 0x249249;
+    /// constant for neighbor calculation
+    pub const T_Y: u32 = 0x492492;
+    /// constant for neighbor calculation
+    pub const T_Z: u32 = 0x924924;
+    /// constants for neighbor calculation, in the order of
+    /// `LinearOctreeNode::DIRECTIONS`
+    pub const DIRECTION_INCREMENTS: [u32; 26] = [
+        0xFFFFFF, // (-1, -1, -1)
+        0xDB6DB6, // (0, -1, -1)
+        0xDB6DB7, // (1, -1, -1)
+        0xB6DB6D, // (-1, 0, -1)
+        0x924924, // (0, 0, -1)
+        0x924925, // (1, 0, -1)
+        0xB6DB6F, // (-1, 1, -1)
+        0x924926, // (0, 1, -1)
+        0x924927, // (1, 1, -1)
+        0x6DB6DB, // (-1, -1, 0)
+        0x492492, // (0, -1, 0)
+        0x492493, // (1, -1, 0)
+        0x249249, // (-1, 0, 0)
+        0x000001, // (1, 0, 0)
+        0x24924B, // (-1, 1, 0)
+        0x000002, // (0, 1, 0)
+        0x000003, // (1, 1, 0)
+        0x6DB6DF, // (-1, -1, 1)
+        0x492496, // (0, -1, 1)
+        0x492497, // (1, -1, 1)
+        0x24924D, // (-1, 0, 1)
+        0x000004, // (0, 0, 1)
+        0x000005, // (1, 0, 1)
+        0x24924F, // (-1, 1, 1)
+        0x000006, // (0, 1, 1)
+        0x000007, // (1, 1, 1)
+    ];
+}
+
+/// Node used for indexing linear octrees
+/// in constant time.
+///
+/// Based on the paper 'Finding Neighbors of Equal Size
+/// in Linear Quadtrees and Octrees in Constant Time'
+/// by Gunther Shrack (1991)
+#[derive(Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Default)]
+pub struct LinearOctreeNode {
+    /// [31:28]: overflow | 4-bit unsigned
+    ///                   | values: 0-15
+    /// [27:24]: level    | 4 bit unsigned
+    ///                   | values: 1-8
+    /// [23:0]: location  | 8 3-bit triples
+    ///                   | values: 8
+    location: u32,
+}
+
+impl LinearOctreeNode {
+    /// Deepest level a key can represent
+    pub const MAX_LEVEL: u32 = consts::RESOLUTION;
+    /// Largest value of the overflow field
+    pub const MAX_OVERFLOW: u32 = 15;
+    /// Offsets along the x, y and z axes of the neighbors returned by
+    /// [`LinearOctreeNode::compute_neighbors`], in the same order
+    pub const DIRECTIONS: [(i32, i32, i32); 26] = [
+        (-1, -1, -1), (0, -1, -1), (1, -1, -1), (-1, 0, -1), (0, 0, -1), (1, 0, -1),
+        (-1, 1, -1), (0, 1, -1), (1, 1, -1), (-1, -1, 0), (0, -1, 0), (1, -1, 0),
+        (-1, 0, 0), (1, 0, 0), (-1, 1, 0), (0, 1, 0), (1, 1, 0), (-1, -1, 1),
+        (0, -1, 1), (1, -1, 1), (-1, 0, 1), (0, 0, 1), (1, 0, 1), (-1, 1, 1),
+        (0, 1, 1), (1, 1, 1),
+    ];
+
+    /// Creates new linear octree node based on location bits
+    /// and level
+    pub fn new(coordinate: u32, level: u32) -> LinearOctreeNode {
+        let location = (level << 24) + coordinate;
+        LinearOctreeNode::from_location(location)
+    }
+
+    /// Creates new linear octree node from raw location data where
+    /// location = coordinate + (level << 24)
+    pub fn from_location(location: u32) -> LinearOctreeNode {
+        LinearOctreeNode {
+            location
+        }
+    }
+
+    /// Returns the keys of the 26 surrounding octree keys of equal
+    /// level, in the order of [`LinearOctreeNode::DIRECTIONS`]. They may
+    /// or may not exist within the linear octree instance but can be
+    /// verified in O(1) time. Directions that fall outside of the
+    /// space boundary are none
+    pub fn compute_neighbors(&self) -> [Option<LinearOctreeNode>; 26] {
+        let mut ret = [None; 26];
+
+        let ni = self.coordinate();
+        let level = self.level();
+        let (x, y, z) = self.grid_position();
+
+        let directions = consts::DIRECTION_INCREMENTS.iter().zip(LinearOctreeNode::DIRECTIONS.iter());
+        for (i, (increment, &(dx, dy, dz))) in directions.enumerate() {
+            let delta_ni = increment << (3 * (consts::RESOLUTION - level));
+
+            let mi =
+                (((ni | consts::T_Y | consts::T_Z) + (delta_ni & consts::T_X)) & consts::T_X) |
+                (((ni | consts::T_X | consts::T_Z) + (delta_ni & consts::T_Y)) & consts::T_Y) |
+                (((ni | consts::T_X | consts::T_Y) + (delta_ni & consts::T_Z)) & consts::T_Z);
+
+            // the increment wraps around at the edge of the space, so
+            // discard anything that did not land at the expected offset
+            let neighbor = LinearOctreeNode::new(mi, level);
+            let (nx, ny, nz) = neighbor.grid_position();
+            if level > 0 &&
+                nx as i32 - x as i32 == dx &&
+                ny as i32 - y as i32 == dy &&
+                nz as i32 - z as i32 == dz {
+                ret[i].replace(neighbor);
+            }
+        }
+        ret
+    }
+
+    /// Returns the column, row and layer of this key within the grid
+    /// of equally sized nodes at its level
+    pub fn grid_position(&self) -> (u32, u32, u32) {
+        let coordinate = self.coordinate() >> (3 * (consts::RESOLUTION - self.level()));
+        let (mut x, mut y, mut z) = (0, 0, 0);
+        for i in 0..self.level() {
+            x |= ((coordinate >> (3 * i)) & 1) << i;
+            y |= ((coordinate >> (3 * i + 1)) & 1) << i;
+            z |= ((coordinate >> (3 * i + 2)) & 1) << i;
+        }
+        (x, y, z)
+    }
+
+    /// Returns true if the regions of the two keys share a face, edge
+    /// or corner without overlapping. Keys may be of different levels
+    pub fn is_adjacent(&self, other: &LinearOctreeNode) -> bool {
+        let ranges = self.finest_range();
+        let other_ranges = other.finest_range();
+
+        let overlaps = ranges.iter().zip(other_ranges.iter())
+            .all(|(&(lo, hi), &(o_lo, o_hi))| lo <= o_hi && o_lo <= hi);
+        let touches = ranges.iter().zip(other_ranges.iter())
+            .all(|(&(lo, hi), &(o_lo, o_hi))| lo <= o_hi + 1 && o_lo <= hi + 1);
+        touches && !overlaps
+    }
+
+    /// Returns the inclusive column, row and layer ranges covered by
+    /// this key in the grid of the deepest level
+    fn finest_range(&self) -> [(u32, u32); 3] {
+        let (x, y, z) = self.grid_position();
+        let shift = consts::RESOLUTION - self.level();
+        [
+            (x << shift, ((x + 1) << shift) - 1),
+            (y << shift, ((y + 1) << shift) - 1),
+            (z << shift, ((z + 1) << shift) - 1)
+        ]
+    }
+
+    #[inline(always)]
+    pub fn octant_at_level(&self, level: u32) -> Octant {
+        assert!((1..=consts::RESOLUTION).contains(&level));
+
+        let location_masked = (self.location >> ((consts::RESOLUTION - level) * 3)) & 0b111;
+        OCTANTS[location_masked as usize]
+    }
+
+    #[inline(always)]
+    pub fn top_octant(&self) -> Octant {
+        self.octant_at_level(self.level())
+    }
+
+    pub fn coordinate_in_octants(&self) -> Vec<Octant> {
+        let mut ret = vec![];
+        for i in 1..=self.level() {
+            ret.push(self.octant_at_level(i));
+        }
+        ret
+    }
+
+    #[inline(always)]
+    pub fn coordinate(&self) -> u32 {
+        self.location & 0xFFFFFF
+    }
+
+    #[inline(always)]
+    pub fn level(&self) -> u32 {
+        self.location >> 24 & 0xF
+    }
+
+    /// Returns some overflow identifier for unique
+    /// identification of keys that belong to the same
+    /// location
+    /// Or none if there is no overflow
+    #[inline(always)]
+    pub fn overflow(&self) -> Option<u32> {
+        let ret = self.location >> 28;
+        match ret {
+            1..=15 => Some(ret),
+            0 => None,
+            _ => unreachable!()
+        }
+    }
+
+    /// Moves this key to the next overflow identifier, or returns
+    /// a `KeyOverflow` error once all of them are in use
+    pub fn increment_overflow(&mut self) -> Result<()> {
+        let overflow = self.overflow().unwrap_or(0) + 1;
+        if overflow > Self::MAX_OVERFLOW {
+            return Err(SpatialError::KeyOverflow);
+        }
+        let mask = !(0xF << 28);
+        self.location = (self.location & mask) | (overflow << 28);
+        Ok(())
+    }
+
+    /// Returns this key with the overflow field cleared
+    pub fn without_overflow(&self) -> Self {
+        LinearOctreeNode::from_location(self.location & !(0xF << 28))
+    }
+
+    pub fn unit_bounds(&self) -> Bounds3D {
+        self.to_bounds(&Bounds3D::default())
+    }
+
+    /// mutates this key to represent further subdivision
+    /// of location based on input octant
+    /// (subset of self). Returns a `DepthExceeded` error if
+    /// the key is already at the deepest level
+    pub fn write_level(&mut self, octant: Octant) -> Result<()> {
+
+        let bits = self.level() + 1;
+        if bits > consts::RESOLUTION {
+            return Err(SpatialError::DepthExceeded);
+        }
+
+        let mask = !(0xF << 24);
+        self.location = (self.location & mask) | (bits << 24);
+
+        let shift = (consts::RESOLUTION - self.level()) * 3;
+        let mask = !(0b111 << shift);
+        self.location = (self.location & mask) | ((octant as u32) << shift);
+        Ok(())
+    }
+
+    /// mutates this key to represent location one level down
+    /// (superset of self)
+    pub fn remove_level(&mut self) {
+        let mask = !(0b111 << ((consts::RESOLUTION - self.level()) * 3));
+        self.location &= mask;
+
+        let bits = self.level().saturating_sub(1) << 24;
+        let mask = !(0xF << 24);
+        self.location = (self.location & mask) | bits;
+    }
+
+    /// returns new key that is one level deeper than self within
+    /// certain input octant. If resolution limit is reached, function will
+    /// return a `DepthExceeded` error
+    pub fn child(&self, octant: Octant) -> Result<Self> {
+        if self.overflow().is_some() {
+            return Err(SpatialError::DepthExceeded);
+        }
+        let mut ret = *self;
+        ret.write_level(octant)?;
+        Ok(ret)
+    }
+
+    /// Returns new key that is one level above self
+    pub fn parent(&self) -> Option<Self> {
+        if self.level() == 0 { return None; }
+        let mut ret = *self;
+        ret.remove_level();
+        Some(ret)
+    }
+
+    pub fn to_bounds<S>(&self, spatial_bound: &Bounds3D<S>) -> Bounds3D<S>
+        where S: Scalar {
+        let mut ret = *spatial_bound;
+        for octant in self.coordinate_in_octants() {
+            ret = ret.sub_bound(octant);
+        }
+        ret
+    }
+}
+
+impl LinearKey for LinearOctreeNode {
+    type Child = Octant;
+
+    const CHILDREN: &'static [Octant] = &OCTANTS;
+    const MAX_LEVEL: u32 = LinearOctreeNode::MAX_LEVEL;
+
+    fn level(&self) -> u32 { LinearOctreeNode::level(self) }
+    fn overflow(&self) -> Option<u32> { LinearOctreeNode::overflow(self) }
+    fn increment_overflow(&mut self) -> Result<()> { LinearOctreeNode::increment_overflow(self) }
+    fn without_overflow(&self) -> Self { LinearOctreeNode::without_overflow(self) }
+    fn child(&self, octant: Octant) -> Result<Self> { LinearOctreeNode::child(self, octant) }
+    fn parent(&self) -> Option<Self> { LinearOctreeNode::parent(self) }
+    fn child_at_level(&self, level: u32) -> Octant { self.octant_at_level(level) }
+    fn neighbors(&self) -> Vec<Self> { self.compute_neighbors().iter().flatten().copied().collect() }
+    fn is_adjacent(&self, other: &Self) -> bool { LinearOctreeNode::is_adjacent(self, other) }
+}
+
+impl std::fmt::Display for LinearOctreeNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = self.coordinate_in_octants()
+            .iter()
+            .map(|octant| format!("{:?}", octant))
+            .collect::<Vec<_>>()
+            .join("|");
+        write!(f, "Key : coordinate: {}, level: {}, overflow: {}",
+               str, self.level(), self.overflow().unwrap_or(0))
+    }
+}
+
+impl std::fmt::Debug for LinearOctreeNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Key {{ location: {:b} }}", self.location)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::LinearOctreeNode;
+    use crate::core::{Octant, Bounds3D};
+
+    #[test]
+    fn test_location_level() {
+        let node1 = LinearOctreeNode::from_location(
+            0b00000011111010000000000000000000
+        );
+        let node2 = LinearOctreeNode::new(
+            0b111010000000000000000000, 3
+        );
+
+        assert_eq!(node1, node2);
+
+        assert_eq!(node1.octant_at_level(1), Octant::BRF);
+        assert_eq!(node1.octant_at_level(2), Octant::BLN);
+        assert_eq!(node1.octant_at_level(3), Octant::TLN);
+        assert_eq!(node1.level(), 3);
+    }
+
+    #[test]
+    fn test_octree_neighbors() {
+        // level 1: far bottom right, level 2: near top left
+        let node: LinearOctreeNode = Default::default();
+        let node = node.child(Octant::BRF).unwrap().child(Octant::TLN).unwrap();
+        assert_eq!(node.grid_position(), (2, 2, 2));
+
+        let neighbors = node.compute_neighbors();
+        for (neighbor, (dx, dy, dz)) in neighbors.iter().zip(LinearOctreeNode::DIRECTIONS.iter()) {
+            let neighbor = neighbor.unwrap();
+            assert_eq!(neighbor.level(), 2);
+            assert_eq!(
+                neighbor.grid_position(),
+                ((2 + dx) as u32, (2 + dy) as u32, (2 + dz) as u32)
+            );
+            assert!(neighbor.is_adjacent(&node));
+        }
+    }
+
+    #[test]
+    fn test_octree_neighbors_at_edge() {
+        // level 2: the corner of the space at the origin
+        let node = LinearOctreeNode::new(0, 2);
+        assert_eq!(node.grid_position(), (0, 0, 0));
+
+        // only the directions pointing away from the origin remain
+        let neighbors = node.compute_neighbors();
+        let found = neighbors.iter().filter(|n| n.is_some()).count();
+        assert_eq!(found, 7);
+        for (neighbor, (dx, dy, dz)) in neighbors.iter().zip(LinearOctreeNode::DIRECTIONS.iter()) {
+            assert_eq!(neighbor.is_some(), *dx >= 0 && *dy >= 0 && *dz >= 0);
+        }
+
+        // at level 1 the space is only two nodes wide
+        let node = LinearOctreeNode::new(0, 1);
+        assert_eq!(node.compute_neighbors().iter().filter(|n| n.is_some()).count(), 7);
+
+        let root: LinearOctreeNode = Default::default();
+        assert!(root.compute_neighbors().iter().all(|n| n.is_none()));
+    }
+
+    #[test]
+    fn test_is_adjacent() {
+        let node: LinearOctreeNode = Default::default();
+        let tln = node.child(Octant::TLN).unwrap();
+        let brf = node.child(Octant::BRF).unwrap();
+        let trn = node.child(Octant::TRN).unwrap();
+
+        assert!(tln.is_adjacent(&brf));
+        assert!(tln.is_adjacent(&trn));
+        assert!(!tln.is_adjacent(&tln));
+        assert!(!tln.is_adjacent(&node));
+
+        // smaller node across the face of a larger one
+        assert!(tln.is_adjacent(&trn.child(Octant::TLN).unwrap()));
+        assert!(!tln.is_adjacent(&trn.child(Octant::TRN).unwrap()));
+    }
+
+    #[test]
+    fn test_parent_child() {
+        let node: LinearOctreeNode = Default::default();
+        let child1 = node.child(Octant::BRN).unwrap();
+        let child2 = child1.child(Octant::TRF).unwrap();
+
+        assert_eq!(child2.coordinate_in_octants(), vec![Octant::BRN, Octant::TRF]);
+        assert_eq!(child2.top_octant(), Octant::TRF);
+        assert_eq!(child2.parent(), Some(child1));
+        assert_eq!(child2.unit_bounds(), Bounds3D::new(0.75, 1., 0.5, 0.75, 0.25, 0.5));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut node = LinearOctreeNode::new(0b011000000000000000000000, 2);
+        assert_eq!(node.overflow(), None);
+
+        node.increment_overflow().unwrap();
+        node.increment_overflow().unwrap();
+        assert_eq!(node.overflow(), Some(2));
+        assert_eq!(node.level(), 2);
+        assert_eq!(node.coordinate(), 0b011000000000000000000000);
+
+        assert_eq!(node.without_overflow(), LinearOctreeNode::new(0b011000000000000000000000, 2));
+        assert!(node.child(Octant::TLN).is_err());
+    }
+}
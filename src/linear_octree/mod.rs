@@ -0,0 +1,7 @@
+mod linear_octree_key;
+#[allow(clippy::module_inception)]
+mod linear_octree;
+
+pub use linear_octree_key::LinearOctreeNode as Key;
+pub use linear_octree::LinearOctree as LinearOctree;
+pub use linear_octree::QueryBounds as QueryBounds;
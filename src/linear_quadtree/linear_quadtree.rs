@@ -1,230 +1,489 @@
 
 
 use crate::linear_quadtree::Key;
-use crate::core::{Spatial2D, Bounds, QUADRANTS};
+use crate::core::{Spatial2D, SpatialKey, SpatialIndex, Bounds, Scalar, Quadrant, QUADRANTS, Candidate, CandidateEntry, LinearMap, MapEntry, Result, SpatialError};
 use hashbrown::HashMap;
-use slotmap::SlotMap;
+use hashbrown::hash_map;
+use std::collections::BinaryHeap;
+use std::iter::FromIterator;
+use std::mem;
 
-new_key_type!{
-    pub struct SpatialKey;
-}
-
-enum QuadtreeEntry<S> {
-    Branch,
-    Leaf(S),
+/// Where an element stored by [`LinearQuadtree::build`] is taken from
+enum Source {
+    /// the leaf at this key of the current map
+    Leaf(Key),
+    /// the new element at this index
+    Item(usize),
 }
 
 #[derive(Default)]
-pub struct LinearQuadtree<S> {
-    spatial_map: HashMap<Key, QuadtreeEntry<S>>,
-    key_map: SlotMap<SpatialKey, Key>,
-    space_boundary: Bounds,
+pub struct LinearQuadtree<S>
+    where S: Spatial2D {
+    map: LinearMap<Key, S>,
+    space_boundary: Bounds<S::Scalar>,
+    auto_grow: bool,
 }
 
-impl<S> LinearQuadtree<S> {
-    pub fn new(space_boundary: Bounds) -> Self {
+impl<S> LinearQuadtree<S>
+    where S: Spatial2D {
+    pub fn new(space_boundary: Bounds<S::Scalar>) -> Self {
+        Self {
+            map: LinearMap::default(),
+            space_boundary,
+            auto_grow: false
+        }
+    }
+
+    /// Creates a tree that accepts elements outside of `space_boundary`
+    /// by doubling the boundary towards them until it holds them.
+    /// Every key is moved one level further down on each growth
+    pub fn with_auto_grow(space_boundary: Bounds<S::Scalar>) -> Self {
         Self {
-            spatial_map: HashMap::new(),
-            key_map: SlotMap::with_key(),
-            space_boundary
+            auto_grow: true,
+            ..Self::new(space_boundary)
+        }
+    }
+
+    /// Returns the region covered by the tree. It only changes
+    /// when a tree created by [`LinearQuadtree::with_auto_grow`] grows
+    pub fn space_boundary(&self) -> Bounds<S::Scalar> {
+        self.space_boundary
+    }
+
+    pub fn get(&self, key: SpatialKey) -> Option<&S> {
+        self.map.get(key)
+    }
+
+    /// Returns a mutable reference to the element at `key`. Changing its
+    /// position does not move it within the tree until
+    /// [`LinearQuadtree::reindex`] is called
+    pub fn get_mut(&mut self, key: SpatialKey) -> Option<&mut S> {
+        self.map.get_mut(key)
+    }
+
+    /// Returns the number of elements in the tree
+    pub fn len(&self) -> usize {
+        self.map.key_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.key_map.is_empty()
+    }
+
+    /// Returns an iterator over every element and its key, in no particular order
+    pub fn iter(&self) -> Iter<'_, S> {
+        Iter {
+            entries: self.map.spatial_map.values(),
+            remaining: self.map.key_map.len()
+        }
+    }
+
+    /// Returns an iterator over every element and its key that allows
+    /// changing them. Moving elements this way does not update their
+    /// position within the tree until [`LinearQuadtree::reindex`] is called
+    pub fn iter_mut(&mut self) -> IterMut<'_, S> {
+        IterMut {
+            entries: self.map.spatial_map.values_mut(),
+            remaining: self.map.key_map.len()
+        }
+    }
+
+    /// Returns an iterator over the key of every element
+    pub fn keys(&self) -> impl Iterator<Item = SpatialKey> + '_ {
+        self.map.key_map.keys()
+    }
+
+    /// Removes every element from the tree and returns an iterator over
+    /// them and their keys. The keys are no longer valid afterwards
+    pub fn drain(&mut self) -> Drain<'_, S> {
+        let remaining = self.map.key_map.len();
+        self.map.key_map.clear();
+        Drain {
+            entries: self.map.spatial_map.drain(),
+            remaining
         }
     }
 }
 
 impl<S> LinearQuadtree<S>
-    where S: Spatial2D + Copy {
+    where S: Spatial2D {
 
     /// Inserts Spatial2D into quadtree and returns
-    /// a persistent key that indexes it
-    pub fn insert(&mut self, loc: S) -> SpatialKey {
+    /// a persistent key that indexes it.
+    ///
+    /// Elements that still share a node at the deepest level of
+    /// the tree are told apart by the overflow bits of their keys.
+    /// Returns a `KeyOverflow` error once all of them are in use
+    pub fn insert(&mut self, loc: S) -> Result<SpatialKey> {
+        self.make_room(&loc)?;
+        self.map.insert(loc, self.space_boundary)
+    }
 
-        let mut ret: Key = Default::default();
-        let mut bounds = self.space_boundary;
+    /// Inserts every element and returns their keys in the same order.
+    /// Returns an `OutOfBounds` error if one lies outside of the space
+    /// boundary and the tree does not grow automatically, or a `KeyOverflow`
+    /// error if more elements share a node at the deepest level than it has
+    /// overflow keys. No element is inserted on error.
+    ///
+    /// Every element is sorted by the Morton code of its position on the
+    /// grid of the deepest level. An empty tree is built from them in a
+    /// single pass without resolving any collisions, while elements loaded
+    /// into a tree that already holds some are inserted in that order
+    pub fn bulk_load<I>(&mut self, items: I) -> Result<Vec<SpatialKey>>
+        where I: IntoIterator<Item = S> {
+        let items: Vec<S> = items.into_iter().collect();
+        if self.is_empty() {
+            return self.load(items).map_err(|(e, _)| e);
+        }
+        for item in &items {
+            self.make_room(item)?;
+        }
+        let results = self.insert_sorted(items);
+        let keys: Result<Vec<SpatialKey>> = results.iter().copied().collect();
+        if keys.is_err() {
+            for key in results.into_iter().flatten() {
+                self.map.remove(key);
+            }
+        }
+        keys
+    }
 
-        loop {
-            let quad = bounds.find_quadrant(&loc);
-            bounds = bounds.sub_bound(quad);
-            println!("Bounds: {:?}", bounds);
-            let mut child = match ret.child(quad) {
-                Ok(child) => child,
-                Err(e) => {
-                    ret.increment_overflow();
-                    ret
-                }
-            };
-            match self.spatial_map.get(&child) {
-                // hit a branch; keep going
-                Some(QuadtreeEntry::Branch) => {
-                    println!("Branch at {}", child);
-                    ret = child
-                },
-                // solve collision by moving both colliding keys
-                // down the tree
-                Some(QuadtreeEntry::Leaf(loc2)) => {
-                    println!("Collision detected");
-                    let loc2 = loc2.clone();
-                    let invalid_key = self.find_key_in_keymap(&child).unwrap();
-                    let old_key = child.clone();
-                    loop {
-                        self.spatial_map.insert(child, QuadtreeEntry::Branch);
-
-                        let quad1 = bounds.find_quadrant(&loc);
-                        let quad2 = bounds.find_quadrant(&loc2);
-
-                        // still collide
-                        if quad1 == quad2 {
-                            bounds = bounds.sub_bound(quad1);
-                            child = match child.child(quad1) {
-                                Ok(child) => child,
-                                _ => panic!("overflow grouping not available yet")
-                            }
-                        }
-                        // seperated after latest subdivision
-                        else {
-                            let child1 = child.child(quad1).unwrap();
-                            let child2 = child.child(quad2).unwrap();
+    /// Inserts the elements one at a time in Morton order, so that
+    /// consecutive inserts descend along the same path, and returns the
+    /// result for each of them in the order they were given
+    fn insert_sorted(&mut self, items: Vec<S>) -> Vec<Result<SpatialKey>> {
+        let mut items: Vec<(Key, usize, S)> = items.into_iter()
+            .enumerate()
+            .map(|(i, s)| (self.deepest_key(&s), i, s))
+            .collect();
+        items.sort_unstable_by_key(|&(key, i, _)| (key, i));
+        let mut results: Vec<(usize, Result<SpatialKey>)> = items.into_iter()
+            .map(|(_, i, s)| (i, self.map.insert(s, self.space_boundary)))
+            .collect();
+        results.sort_unstable_by_key(|&(i, _)| i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
 
-                            println!("New child: {}", child1);
-                            println!("Old child: {} to {}", old_key, child2);
-                            println!("-----------------------");
+    /// Bulk loads `items`, handing them back along with the error
+    /// if they can not be inserted
+    fn load(&mut self, items: Vec<S>) -> std::result::Result<Vec<SpatialKey>, (SpatialError, Vec<S>)> {
+        for item in &items {
+            if let Err(e) = self.make_room(item) {
+                return Err((e, items));
+            }
+        }
+        self.build(items)
+    }
 
-                            self.spatial_map.insert(child1, QuadtreeEntry::Leaf(loc));
-                            self.spatial_map.insert(child2, QuadtreeEntry::Leaf(loc2));
+    /// Rebuilds the map from the current elements and `items`, and returns
+    /// the keys of `items`. Each element is stored one level below the
+    /// deepest node it shares with the elements next to it in Morton order,
+    /// along with the branches above it that the element before did not add.
+    /// The tree is left unchanged on error
+    fn build(&mut self, items: Vec<S>) -> std::result::Result<Vec<SpatialKey>, (SpatialError, Vec<S>)> {
+        let mut elements: Vec<(Key, Source)> = self.map.spatial_map.iter()
+            .filter_map(|(&key, entry)| match entry {
+                MapEntry::Leaf(_, s) => Some((self.deepest_key(s), Source::Leaf(key))),
+                MapEntry::Branch => None
+            })
+            .chain(items.iter().enumerate().map(|(i, s)| (self.deepest_key(s), Source::Item(i))))
+            .collect();
+        elements.sort_unstable_by_key(|&(key, _)| key);
 
-                            *self.key_map.get_mut(invalid_key).unwrap() = child2;
-                            return self.key_map.insert(child1);
-                        }
-                    }
+        // the leaf of every element and the level of the deepest branch
+        // above it already added, so the map is allocated once
+        let mut leaves = Vec::with_capacity(elements.len());
+        let mut size = 0;
+        let mut last = Key::default();
+        for (i, &(key, _)) in elements.iter().enumerate() {
+            let before = match i {
+                0 => 0,
+                _ => key.common_level(&elements[i - 1].0)
+            };
+            let after = elements.get(i + 1).map_or(0, |next| key.common_level(&next.0));
+
+            let leaf = if before == Key::MAX_LEVEL {
+                // shares the deepest node with the element before
+                let mut leaf = last;
+                if let Err(e) = leaf.increment_overflow() {
+                    return Err((e, items));
                 }
-                // empty value, take coordinates for this spatial
-                None => {
-                    ret = child;
-                    self.spatial_map.insert(ret, QuadtreeEntry::Leaf(loc));
-                    println!("Inserted: {}", ret);
-                    println!("-----------------------");
-                    return self.key_map.insert(ret);
+                leaf
+            } else {
+                key.ancestor(before.max(after) + 1)
+            };
+            size += leaf.level().saturating_sub(before + 1) as usize + 1;
+            leaves.push((leaf, before));
+            last = leaf;
+        }
+
+        let mut old_map = mem::replace(&mut self.map.spatial_map, HashMap::with_capacity(size));
+        let mut items: Vec<Option<S>> = items.into_iter().map(Some).collect();
+        let mut keys = vec![SpatialKey::default(); items.len()];
+        for (&(leaf, before), &(key, ref source)) in leaves.iter().zip(&elements) {
+            for level in before + 1..leaf.level() {
+                self.map.spatial_map.insert(key.ancestor(level), MapEntry::Branch);
+            }
+            let (spatial_key, s) = match *source {
+                Source::Leaf(k) => match old_map.remove(&k) {
+                    Some(MapEntry::Leaf(spatial_key, s)) => (spatial_key, s),
+                    _ => unreachable!()
+                },
+                Source::Item(i) => {
+                    keys[i] = self.map.key_map.insert(leaf);
+                    (keys[i], items[i].take().unwrap())
                 }
             };
-        };
+            let stored = self.map.insert_leaf(spatial_key, leaf, s);
+            debug_assert!(stored.is_ok(), "bulk built leaves are distinct");
+        }
+        Ok(keys)
+    }
+
+    /// Returns the key of the node at the deepest level that holds `loc`,
+    /// which is its Morton code on the grid of that level
+    fn deepest_key(&self, loc: &S) -> Key {
+        let mut key = Key::default();
+        let mut bounds = self.space_boundary;
+        loop {
+            let (quadrant, sub_bound) = bounds.split_towards(loc);
+            if key.write_level(quadrant).is_err() {
+                return key;
+            }
+            bounds = sub_bound;
+        }
     }
 
     pub fn remove(&mut self, key: SpatialKey) -> Option<S> {
-        if let Some(k) = self.key_map.remove(key) {
-            if let Some(QuadtreeEntry::Leaf(s)) = self.spatial_map.remove(&k) {
-
-                // there are three possibilities:
-                //  1: this location has two or more leaf siblings: do nothing as these points
-                // need their current spatial levels to remain separated
-                //  2: this location has at least one branch as a sibling: do nothing as
-                // the deeper levels in that branch require being farther down in the tree
-                //  3: this location has only one leaf sibling: reduce the level of that sibling
-                // so that spatial complexity can be recovered from the removal of this element
-
-                let top_level_quadrant = k.top_quadrant();
-                let parent = k.parent().unwrap();
-                let mut child_count = 0;
-                let mut relocate = true;
-
-                // check sibling quadrants for leaves or branches
-                for (i, &quadrant) in QUADRANTS.iter().enumerate() {
-                    if quadrant == top_level_quadrant {continue;}
-                    let subparent = parent.child(quadrant).unwrap();
-                    match self.spatial_map.get(&subparent) {
-                        Some(QuadtreeEntry::Branch) => relocate = false,
-                        Some(QuadtreeEntry::Leaf(_)) => child_count += 1,
-                        _ => ()
-                    }
-                }
+        self.map.remove(key)
+    }
 
-                if child_count != 1 || k.level() <= 1 {
-                    relocate = false;
-                }
+    /// Keeps only the elements for which `f` returns true
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(SpatialKey, &S) -> bool {
+        let removed: Vec<SpatialKey> = self.iter()
+            .filter(|&(key, s)| !f(key, s))
+            .map(|(key, _)| key)
+            .collect();
+        for key in removed {
+            self.remove(key);
+        }
+    }
 
-                // if there is only one leaf sibling, we must relocate it up the tree.
-                // otherwise do nothing
-                if relocate {
-                    let (mut key_to_move, mut s_to_move) = (None, None);
-                    for &quadrant in QUADRANTS.iter() {
-                        if quadrant == top_level_quadrant {continue;}
-                        let key = parent.child(quadrant).unwrap();
-                        if let Some(QuadtreeEntry::Leaf(s)) = self.spatial_map.remove(&key) {
-                            key_to_move.replace(key);
-                            s_to_move.replace(s);
-                        }
-                    };
-
-                    let invalid_key = self.find_key_in_keymap(&key_to_move.unwrap()).unwrap();
-                    let s_to_move = s_to_move.unwrap();
-                    let mut parent = parent;
-
-                    loop {
-                        // safe to remove parent branch as we know there is nothing below it
-                        self.spatial_map.remove(&parent);
-                        let parents_parent = parent.parent();
-                        if parents_parent.is_none() || parent.level() == 1 {
-                            self.spatial_map.insert(parent, QuadtreeEntry::Leaf(s_to_move));
-
-                            // validate key
-                            *self.key_map.get_mut(invalid_key).unwrap() = parent;
-                            break;
-                        }
+    /// Places every element that no longer lies within its node again,
+    /// such as ones moved through [`LinearQuadtree::iter_mut`]. Elements
+    /// that can not be placed again, usually because they moved out of
+    /// the space boundary, are removed and returned with their keys
+    pub fn reindex(&mut self) -> Vec<(SpatialKey, S)> {
+        if self.auto_grow {
+            let outside: Vec<[S::Scalar; 2]> = self.iter()
+                .map(|(_, s)| [s.x(), s.y()])
+                .filter(|p| !self.space_boundary.contains_point(p))
+                .collect();
+            for p in outside {
+                // elements that do not fit are removed below
+                let _ = self.make_room(&p);
+            }
+        }
+        self.map.reindex(self.space_boundary)
+    }
+
+    /// Replaces the element at `key` with `loc` and returns the old element,
+    /// or none if there is no element at `key`.
+    ///
+    /// If `loc` lies in the same node as the old element only the stored
+    /// value changes. Otherwise the element is removed from its node and
+    /// placed again starting from the deepest branch that holds both the old
+    /// and new location. The key stays valid either way
+    pub fn update(&mut self, key: SpatialKey, loc: S) -> Result<Option<S>> {
+        if !self.map.key_map.contains_key(key) {
+            return Ok(None);
+        }
+        // growing the tree changes the key of the element
+        self.make_room(&loc)?;
+        self.map.update(key, loc, self.space_boundary)
+    }
 
-                        let parents_parent = parents_parent.unwrap();
-                        if self.num_child(parents_parent) == 0 {
-                            self.spatial_map.insert(parents_parent, QuadtreeEntry::Leaf(s_to_move));
+    /// Calls `f` on every element and re-indexes the ones that moved.
+    ///
+    /// Unless the tree grows automatically, elements that `f` moves out of
+    /// the space boundary are removed and returned along with their keys
+    pub fn move_all<F>(&mut self, mut f: F) -> Vec<(SpatialKey, S)>
+        where F: FnMut(SpatialKey, &mut S) {
+        for (key, s) in self.iter_mut() {
+            f(key, s);
+        }
+        self.reindex()
+    }
 
-                            // validate key
-                            *self.key_map.get_mut(invalid_key).unwrap() = parent;
-                            break;
-                        }
+    /// Grows the space boundary until it holds `loc` if the tree grows
+    /// automatically, or returns an `OutOfBounds` error otherwise
+    fn make_room(&mut self, loc: &dyn Spatial2D<Scalar = S::Scalar>) -> Result<()> {
+        while !self.space_boundary.contains_point(loc) {
+            let grown = if self.auto_grow { self.space_boundary.double_towards(loc) } else { None };
+            match grown {
+                Some((bounds, quadrant)) => self.grow(bounds, quadrant)?,
+                None => return Err(SpatialError::out_of_bounds(loc.pos(), self.space_boundary))
+            }
+        }
+        Ok(())
+    }
 
-                        parent = parents_parent;
-                    }
+    /// Replaces the space boundary with `bounds`, twice its size, keeping the
+    /// old root as the branch at `quadrant`. Every key is re-prefixed with
+    /// that quadrant, and the elements that fall into another node within
+    /// the new boundary are placed again
+    fn grow(&mut self, bounds: Bounds<S::Scalar>, quadrant: Quadrant) -> Result<()> {
+        let old_bounds = self.space_boundary;
+        // a lone element moves up to the first level rather than down,
+        // and keys at the deepest level can not move down at all
+        let deepest = self.map.key_map.values().any(|k| k.level() == Key::MAX_LEVEL);
+        if self.map.key_map.len() < 2 || deepest {
+            return self.rebuild(bounds);
+        }
+
+        for (key, entry) in mem::take(&mut self.map.spatial_map) {
+            let key = key.wrap(quadrant)?;
+            if let MapEntry::Leaf(spatial_key, _) = entry {
+                self.map.key_map[spatial_key] = key;
+            }
+            self.map.spatial_map.insert(key, entry);
+        }
+        self.map.spatial_map.insert(Key::default().child(quadrant)?, MapEntry::Branch);
+        self.space_boundary = bounds;
+
+        // elements on the edges of the old boundary now lie in the
+        // quadrants next to the old root
+        let mut failed = vec![];
+        let mut error = None;
+        for spatial_key in self.map.misplaced(bounds) {
+            if let Some((_, s)) = self.map.detach(self.map.key_map[spatial_key]) {
+                if let Err((e, s)) = self.map.place(spatial_key, s, Key::default(), bounds) {
+                    failed.push((spatial_key, s));
+                    error = Some(e);
                 }
-                return Some(s);
             }
         }
-        None
+        match error {
+            Some(e) => {
+                self.restore(old_bounds, failed);
+                Err(e)
+            }
+            None => Ok(())
+        }
+    }
+
+    /// Places every element and the detached `elements` again within
+    /// `bounds`, the space boundary they all fit into before growing
+    fn restore(&mut self, bounds: Bounds<S::Scalar>, mut elements: Vec<(SpatialKey, S)>) {
+        self.space_boundary = bounds;
+        elements.extend(mem::take(&mut self.map.spatial_map)
+            .into_iter()
+            .filter_map(|(_, entry)| match entry {
+                MapEntry::Leaf(spatial_key, s) => Some((spatial_key, s)),
+                MapEntry::Branch => None
+            }));
+        for (spatial_key, s) in elements {
+            let placed = self.map.place(spatial_key, s, Key::default(), bounds);
+            debug_assert!(placed.is_ok(), "elements fit into the space boundary they came from");
+        }
     }
 
-    pub fn neighbors(&self, key: Key) -> Vec<&S> {
-        unimplemented!()
+    /// Places every element again within `bounds`, or leaves the
+    /// tree unchanged if they do not fit into it
+    fn rebuild(&mut self, bounds: Bounds<S::Scalar>) -> Result<()> {
+        let old_bounds = mem::replace(&mut self.space_boundary, bounds);
+        if let Err((e, _)) = self.build(vec![]) {
+            self.space_boundary = old_bounds;
+            return Err(e);
+        }
+        Ok(())
     }
 
-    pub fn neighbors_mut(&mut self, key: Key) -> Vec<&mut S> {
-        unimplemented!()
+    /// Returns every element whose node shares an edge or corner with
+    /// the node of the element at `key`. This includes smaller nodes inside
+    /// a larger neighboring branch and larger nodes further up the tree
+    pub fn neighbors(&self, key: SpatialKey) -> Vec<&S> {
+        self.map.neighboring_keys(key)
+            .into_iter()
+            .filter_map(|k| self.map.leaf(k))
+            .collect()
     }
 
-    pub fn neighbors_within(&self, s: S, radius: f32) -> Vec<&S> { unimplemented!() }
+    /// Mutable version of [`LinearQuadtree::neighbors`]. Moving the returned
+    /// elements does not update their position within the tree
+    pub fn neighbors_mut(&mut self, key: SpatialKey) -> Vec<&mut S> {
+        let keys = self.map.neighboring_keys(key);
+        self.map.leaves_mut(&keys)
+    }
 
-    pub fn neighbors_within_mut(&mut self, s: S, radius: f32) -> Vec<&mut S> { unimplemented!() }
+    /// Returns every element within `radius` of the element at `key`,
+    /// not including the element itself
+    pub fn neighbors_within(&self, key: SpatialKey, radius: S::Scalar) -> Vec<&S> {
+        self.keys_within(key, radius)
+            .into_iter()
+            .filter_map(|k| self.map.leaf(k))
+            .collect()
+    }
 
-    pub fn values<'a>(&'a self) -> Vec<&'a S> {
-        let mut ret = Vec::new();
-        for key in self.key_map.keys() {
-            if let QuadtreeEntry::Leaf(s) = self.spatial_map.get(self.key_map.get(key).unwrap()).unwrap(){
-                ret.push(s);
-            }
+    /// Mutable version of [`LinearQuadtree::neighbors_within`]. Moving the
+    /// returned elements does not update their position within the tree
+    pub fn neighbors_within_mut(&mut self, key: SpatialKey, radius: S::Scalar) -> Vec<&mut S> {
+        let keys = self.keys_within(key, radius);
+        self.map.leaves_mut(&keys)
+    }
+
+    /// Returns an iterator over every element inside `query`, visiting
+    /// only the keys whose region intersects it
+    pub fn query_bounds(&self, query: &Bounds<S::Scalar>) -> QueryBounds<'_, S> {
+        let mut iter = QueryBounds {
+            tree: self,
+            query: *query,
+            stack: vec![]
+        };
+        if self.space_boundary.intersects(*query) {
+            iter.push_children(Key::default(), self.space_boundary);
         }
-        ret
+        iter
     }
 
-//    pub fn values_mut<'a>(&'a mut self) -> Vec<&'a mut S> {
-//        let mut ret = Vec::new();
-//        unsafe {
-//            for key in self.key_map.keys() {
-//                if let QuadtreeEntry::Leaf(s) = self.spatial_map.get_mut(self.key_map.get(key).unwrap()).unwrap() {
-//                    ret.push(s);
-//                }
-//            }
-//        }
-//        ret
-//    }
+    /// Returns up to `k` elements closest to `p` along with their distances,
+    /// sorted from nearest to farthest. If `max_dist` is given, elements
+    /// farther away than it are not returned
+    pub fn k_nearest(&self, p: &dyn Spatial2D<Scalar = S::Scalar>, k: usize, max_dist: Option<S::Scalar>) -> Vec<(&S, S::Scalar)> {
+        self.nearest(p)
+            .take_while(|&(_, _, _, squared)| match max_dist {
+                Some(max) => S::Scalar::within_radius(squared, max),
+                None => true
+            })
+            .take(k)
+            .map(|(_, _, s, squared)| (s, S::Scalar::from_squared(squared)))
+            .collect()
+    }
+
+    /// Returns an iterator over elements and their keys in order of increasing distance to `p`
+    fn nearest(&self, p: &dyn Spatial2D<Scalar = S::Scalar>) -> Nearest<'_, S> {
+        let point = [p.x(), p.y()];
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate::node(
+            self.space_boundary.squared_distance_to_point(&point),
+            (Key::default(), self.space_boundary)
+        ));
+        Nearest {
+            tree: self,
+            point,
+            queue
+        }
+    }
+
+    pub fn values(&self) -> Vec<&S> {
+        self.iter().map(|(_, s)| s).collect()
+    }
 
     /// Returns all bounds that make up the hierarchy of the quadtree
-    pub fn bounds(&self) -> Vec<Bounds> {
+    pub fn bounds(&self) -> Vec<Bounds<S::Scalar>> {
         let mut ret = Vec::new();
-        for key in self.spatial_map.keys() {
+        for key in self.map.spatial_map.keys() {
             ret.push(key.to_bounds(&self.space_boundary));
         }
         ret
@@ -232,62 +491,704 @@ impl<S> LinearQuadtree<S>
 
     /// Returns the bounds of every node on the tree that contains
     /// a Spatial element, skipping branches
-    pub fn bounds_no_branch(&self) -> Vec<Bounds> {
+    pub fn bounds_no_branch(&self) -> Vec<Bounds<S::Scalar>> {
         let mut ret = Vec::new();
-        for (_, key) in &self.key_map {
+        for (_, key) in &self.map.key_map {
             ret.push(key.to_bounds(&self.space_boundary));
         }
         ret
     }
 
-    fn find_key_in_keymap(&mut self, spatial_key: &Key) -> Option<SpatialKey> {
-        for (key, _spatial_key) in self.key_map.iter() {
-            if(*_spatial_key == *spatial_key) {
-                return Some(key);
+    /// Returns the keys of every leaf within `radius` of the leaf at `key`,
+    /// not including `key` itself
+    fn keys_within(&self, key: SpatialKey, radius: S::Scalar) -> Vec<Key> {
+        let key = match self.map.key_map.get(key) {
+            Some(key) => *key,
+            None => return vec![]
+        };
+        let center = match self.map.spatial_map.get(&key) {
+            Some(MapEntry::Leaf(_, s)) => [s.x(), s.y()],
+            _ => return vec![]
+        };
+        self.nearest(&center)
+            .take_while(|&(_, _, _, squared)| S::Scalar::within_radius(squared, radius))
+            .map(|(k, _, _, _)| k)
+            .filter(|k| *k != key)
+            .collect()
+    }
+}
+
+impl<S> SpatialIndex for LinearQuadtree<S>
+    where S: Spatial2D {
+    type Item = S;
+
+    fn insert(&mut self, item: S) -> Result<SpatialKey> {
+        LinearQuadtree::insert(self, item)
+    }
+
+    fn remove_by_key(&mut self, key: SpatialKey) -> Option<S> {
+        LinearQuadtree::remove(self, key)
+    }
+
+    fn get(&self, key: SpatialKey) -> Option<&S> {
+        LinearQuadtree::get(self, key)
+    }
+
+    fn len(&self) -> usize {
+        LinearQuadtree::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (SpatialKey, &S)> + '_> {
+        Box::new(LinearQuadtree::iter(self))
+    }
+
+    fn query_bounds_keyed(&self, query: &Bounds<S::Scalar>) -> Box<dyn Iterator<Item = (SpatialKey, &S)> + '_> {
+        let mut query = LinearQuadtree::query_bounds(self, query);
+        Box::new(std::iter::from_fn(move || query.next_entry()))
+    }
+
+    fn within_keyed(&self, p: &dyn Spatial2D<Scalar = S::Scalar>, radius: S::Scalar) -> Vec<(SpatialKey, &S)> {
+        self.nearest(p)
+            .take_while(|&(_, _, _, squared)| S::Scalar::within_radius(squared, radius))
+            .map(|(_, spatial_key, s, _)| (spatial_key, s))
+            .collect()
+    }
+
+    fn k_nearest_keyed(&self, p: &dyn Spatial2D<Scalar = S::Scalar>, k: usize) -> Vec<(SpatialKey, &S, S::Scalar)> {
+        self.nearest(p)
+            .take(k)
+            .map(|(_, spatial_key, s, squared)| (spatial_key, s, S::Scalar::from_squared(squared)))
+            .collect()
+    }
+}
+
+impl<S> FromIterator<S> for LinearQuadtree<S>
+    where S: Spatial2D {
+    /// Creates a tree around the elements that grows automatically
+    fn from_iter<I>(items: I) -> Self
+        where I: IntoIterator<Item = S> {
+        let items: Vec<S> = items.into_iter().collect();
+        let mut tree = LinearQuadtree::with_auto_grow(Bounds::around_points(&items));
+        tree.extend(items);
+        tree
+    }
+}
+
+impl<S> Extend<S> for LinearQuadtree<S>
+    where S: Spatial2D {
+    /// Bulk loads the elements, skipping the ones that can not be inserted
+    fn extend<I>(&mut self, items: I)
+        where I: IntoIterator<Item = S> {
+        let items: Vec<S> = items.into_iter()
+            .filter(|item| self.make_room(item).is_ok())
+            .collect();
+        if !self.is_empty() {
+            self.insert_sorted(items);
+            return;
+        }
+        // too many elements share a node at the deepest level,
+        // so they are inserted one at a time to keep the ones that fit
+        if let Err((_, items)) = self.load(items) {
+            self.insert_sorted(items);
+        }
+    }
+}
+
+impl<'a, S> IntoIterator for &'a LinearQuadtree<S>
+    where S: Spatial2D {
+    type Item = (SpatialKey, &'a S);
+    type IntoIter = Iter<'a, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, S> IntoIterator for &'a mut LinearQuadtree<S>
+    where S: Spatial2D {
+    type Item = (SpatialKey, &'a mut S);
+    type IntoIter = IterMut<'a, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<S> IntoIterator for LinearQuadtree<S>
+    where S: Spatial2D {
+    type Item = (SpatialKey, S);
+    type IntoIter = IntoIter<S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            remaining: self.map.key_map.len(),
+            entries: self.map.spatial_map.into_iter()
+        }
+    }
+}
+
+/// Iterator over the elements of a tree and their keys, created by
+/// [`LinearQuadtree::iter`]
+pub struct Iter<'a, S> {
+    entries: hash_map::Values<'a, Key, MapEntry<S>>,
+    remaining: usize,
+}
+
+impl<'a, S> Iterator for Iter<'a, S> {
+    type Item = (SpatialKey, &'a S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for entry in &mut self.entries {
+            if let MapEntry::Leaf(spatial_key, s) = entry {
+                self.remaining -= 1;
+                return Some((*spatial_key, s));
             }
         }
         None
     }
 
-    fn neighboring_keys(&self, key: Key) -> Vec<Key> {
-        let mut ret = Vec::new();
-        for same_size_key in key.compute_neighbors().iter(){
-            if same_size_key.is_none() {continue;}
-            let same_size_key = same_size_key.unwrap();
-            match self.spatial_map.get(&same_size_key) {
-                Some(QuadtreeEntry::Branch) => {
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
 
+impl<'a, S> ExactSizeIterator for Iter<'a, S> {}
+
+/// Mutable iterator over the elements of a tree and their keys,
+/// created by [`LinearQuadtree::iter_mut`]
+pub struct IterMut<'a, S> {
+    entries: hash_map::ValuesMut<'a, Key, MapEntry<S>>,
+    remaining: usize,
+}
+
+impl<'a, S> Iterator for IterMut<'a, S> {
+    type Item = (SpatialKey, &'a mut S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for entry in &mut self.entries {
+            if let MapEntry::Leaf(spatial_key, s) = entry {
+                self.remaining -= 1;
+                return Some((*spatial_key, s));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, S> ExactSizeIterator for IterMut<'a, S> {}
+
+/// Iterator over the elements removed by [`LinearQuadtree::drain`]
+pub struct Drain<'a, S> {
+    entries: hash_map::Drain<'a, Key, MapEntry<S>>,
+    remaining: usize,
+}
+
+impl<'a, S> Iterator for Drain<'a, S> {
+    type Item = (SpatialKey, S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (_, entry) in &mut self.entries {
+            if let MapEntry::Leaf(spatial_key, s) = entry {
+                self.remaining -= 1;
+                return Some((spatial_key, s));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, S> ExactSizeIterator for Drain<'a, S> {}
+
+/// Owning iterator over the elements of a tree and their keys
+pub struct IntoIter<S> {
+    entries: hash_map::IntoIter<Key, MapEntry<S>>,
+    remaining: usize,
+}
+
+impl<S> Iterator for IntoIter<S> {
+    type Item = (SpatialKey, S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (_, entry) in &mut self.entries {
+            if let MapEntry::Leaf(spatial_key, s) = entry {
+                self.remaining -= 1;
+                return Some((spatial_key, s));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<S> ExactSizeIterator for IntoIter<S> {}
+
+/// Iterator over the elements inside a query region, created by
+/// [`LinearQuadtree::query_bounds`]
+pub struct QueryBounds<'a, S>
+    where S: Spatial2D {
+    tree: &'a LinearQuadtree<S>,
+    query: Bounds<S::Scalar>,
+    stack: Vec<(Key, Bounds<S::Scalar>)>,
+}
+
+impl<'a, S> QueryBounds<'a, S>
+    where S: Spatial2D {
+    fn push_children(&mut self, key: Key, bounds: Bounds<S::Scalar>) {
+        for &quadrant in QUADRANTS.iter() {
+            if let Ok(child) = key.child(quadrant) {
+                let child_bounds = bounds.sub_bound(quadrant);
+                if child_bounds.intersects(self.query) {
+                    self.stack.push((child, child_bounds));
                 }
-                Some(QuadtreeEntry::Leaf(_)) => {
-                    ret.push(key);
-                }
-                None => {
-                    let mut parent_key = same_size_key.clone();
-                    while !self.spatial_map.contains_key(&parent_key){
-                        parent_key = parent_key.parent().unwrap();
+            }
+        }
+    }
+
+    fn next_entry(&mut self) -> Option<(SpatialKey, &'a S)> {
+        while let Some((key, bounds)) = self.stack.pop() {
+            match self.tree.map.spatial_map.get(&key) {
+                Some(MapEntry::Branch) => self.push_children(key, bounds),
+                Some(MapEntry::Leaf(spatial_key, s)) => {
+                    if key.overflow().is_none() {
+                        for overflow_key in self.tree.map.bucket_keys(key).skip(1) {
+                            self.stack.push((overflow_key, bounds));
+                        }
+                    }
+                    if self.query.contains_point(s) {
+                        return Some((*spatial_key, s));
                     }
-                    ret.push(parent_key);
                 }
+                None => ()
             }
         }
-        ret.sort_by(|a, b| a.cmp(b) );
-        ret.dedup();
-        ret
+        None
+    }
+}
+
+impl<'a, S> Iterator for QueryBounds<'a, S>
+    where S: Spatial2D {
+    type Item = &'a S;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().map(|(_, s)| s)
     }
+}
+
+type NearestCandidate<'a, S> = Candidate<
+    (Key, Bounds<<S as Spatial2D>::Scalar>),
+    (Key, SpatialKey, &'a S),
+    <<S as Spatial2D>::Scalar as Scalar>::Squared
+>;
 
-    fn num_child(&self, key: Key) -> u32 {
-        match self.spatial_map.get(&key) {
-            Some(QuadtreeEntry::Leaf(_)) => 1,
-            Some(QuadtreeEntry::Branch) => {
-                let mut ret = 0;
-                for quadrant in &crate::core::QUADRANTS {
-                    if let Ok(child) = key.child(*quadrant) {
-                        ret += self.num_child(child);
+/// Best-first traversal yielding elements, their keys and their squared distances to a point,
+/// nearest first. Branches are expanded lazily in order of their minimum
+/// possible distance
+struct Nearest<'a, S>
+    where S: Spatial2D {
+    tree: &'a LinearQuadtree<S>,
+    point: [S::Scalar; 2],
+    queue: BinaryHeap<NearestCandidate<'a, S>>,
+}
+
+impl<'a, S> Iterator for Nearest<'a, S>
+    where S: Spatial2D {
+    type Item = (Key, SpatialKey, &'a S, <S::Scalar as Scalar>::Squared);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Candidate { distance, entry }) = self.queue.pop() {
+            match entry {
+                CandidateEntry::Item((key, spatial_key, s)) => return Some((key, spatial_key, s, distance)),
+                CandidateEntry::Node((key, bounds)) => {
+                    for &quadrant in QUADRANTS.iter() {
+                        let child = match key.child(quadrant) {
+                            Ok(child) => child,
+                            Err(_) => continue
+                        };
+                        let child_bounds = bounds.sub_bound(quadrant);
+                        match self.tree.map.spatial_map.get(&child) {
+                            Some(MapEntry::Branch) => self.queue.push(Candidate::node(
+                                child_bounds.squared_distance_to_point(&self.point),
+                                (child, child_bounds)
+                            )),
+                            Some(MapEntry::Leaf(..)) => {
+                                for leaf in self.tree.map.bucket_keys(child) {
+                                    if let Some(MapEntry::Leaf(spatial_key, s)) = self.tree.map.spatial_map.get(&leaf) {
+                                        self.queue.push(Candidate::item(
+                                            s.squared_distance_to(&self.point),
+                                            (leaf, *spatial_key, s)
+                                        ));
+                                    }
+                                }
+                            }
+                            None => ()
+                        }
                     }
                 }
-                ret
             }
-            _ => 0,
         }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LinearQuadtree;
+    use crate::core::{Bounds, Point2D, Spatial2D, SpatialError};
+
+    /// Element that owns heap data and can not be copied
+    #[derive(Debug, PartialEq)]
+    struct Named {
+        name: String,
+        pos: [f32; 2],
+    }
+
+    impl Spatial2D for Named {
+        type Scalar = f32;
+        fn x(&self) -> f32 {self.pos[0]}
+        fn y(&self) -> f32 {self.pos[1]}
+    }
+
+    fn named(name: &str, x: f32, y: f32) -> Named {
+        Named { name: name.to_string(), pos: [x, y] }
+    }
+
+    #[test]
+    fn test_query_bounds() {
+        let mut tree = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        for i in 0..10 {
+            for j in 0..10 {
+                tree.insert(Point2D::new(i as f32 * 10. + 5., j as f32 * 10. + 5.)).unwrap();
+            }
+        }
+
+        let mut found: Vec<Point2D> = tree.query_bounds(&Bounds::new(20., 40., 60., 75.)).cloned().collect();
+        found.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+        assert_eq!(found, vec![
+            Point2D::new(25., 65.), Point2D::new(25., 75.),
+            Point2D::new(35., 65.), Point2D::new(35., 75.),
+        ]);
+
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 100., 0., 100.)).count(), 100);
+        assert_eq!(tree.query_bounds(&Bounds::new(1., 4., 1., 4.)).count(), 0);
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let mut tree = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+
+        // one point per top level quadrant, then subdivide the
+        // right half so that it holds smaller leaves
+        let left_bottom = tree.insert(Point2D::new(20., 20.)).unwrap();
+        tree.insert(Point2D::new(20., 80.)).unwrap();
+        tree.insert(Point2D::new(60., 10.)).unwrap();
+        tree.insert(Point2D::new(90., 10.)).unwrap();
+        tree.insert(Point2D::new(60., 40.)).unwrap();
+        let far = tree.insert(Point2D::new(90., 40.)).unwrap();
+
+        let mut found: Vec<Point2D> = tree.neighbors(left_bottom).into_iter().cloned().collect();
+        found.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+        assert_eq!(found, vec![
+            Point2D::new(20., 80.),
+            Point2D::new(60., 10.),
+            Point2D::new(60., 40.),
+        ]);
+
+        // the larger leaf covering the whole top left quadrant is found
+        // from a small leaf on the other side
+        let mut found: Vec<Point2D> = tree.neighbors(far).into_iter().cloned().collect();
+        found.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+        assert_eq!(found, vec![
+            Point2D::new(60., 10.),
+            Point2D::new(60., 40.),
+            Point2D::new(90., 10.),
+        ]);
+
+        for s in tree.neighbors_mut(left_bottom) {
+            s.x += 1.;
+        }
+        assert_eq!(tree.neighbors(far).len(), 3);
+
+        let found = tree.neighbors_within(left_bottom, 45.);
+        assert_eq!(found, vec![&Point2D::new(61., 10.)]);
+    }
+
+    #[test]
+    fn test_neighbors_mut() {
+        let mut tree = LinearQuadtree::new(Bounds::new(0., 64., 0., 64.));
+        let mut center = None;
+        for i in 0..32 {
+            for j in 0..32 {
+                let key = tree.insert(Point2D::new(i as f32 * 2. + 1., j as f32 * 2. + 1.)).unwrap();
+                if (i, j) == (10, 20) {
+                    center = Some(key);
+                }
+            }
+        }
+        let center = center.unwrap();
+
+        // only the nodes around the center change among all the others
+        for s in tree.neighbors_within_mut(center, 2.1) {
+            s.y += 0.25;
+        }
+        for s in tree.neighbors_mut(center) {
+            s.x += 0.5;
+        }
+        let mut moved: Vec<Point2D> = tree.iter()
+            .map(|(_, p)| *p)
+            .filter(|p| p.x.fract() != 0. || p.y.fract() != 0.)
+            .collect();
+        moved.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+        assert_eq!(moved, vec![
+            Point2D::new(19.5, 39.), Point2D::new(19.5, 41.25), Point2D::new(19.5, 43.),
+            Point2D::new(21.5, 39.25), Point2D::new(21.5, 43.25),
+            Point2D::new(23.5, 39.), Point2D::new(23.5, 41.25), Point2D::new(23.5, 43.),
+        ]);
+        assert_eq!(tree.get(center), Some(&Point2D::new(21., 41.)));
+    }
+
+    #[test]
+    fn test_coincident_points() {
+        let mut tree = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let other = tree.insert(Point2D::new(80., 80.)).unwrap();
+        let keys: Vec<_> = (0..16)
+            .map(|i| tree.insert(Point2D::new(10., 10. + i as f32 * 1e-6)).unwrap())
+            .collect();
+
+        // the deepest node is full
+        assert!(tree.insert(Point2D::new(10., 10.)).is_err());
+
+        assert_eq!(tree.values().len(), 17);
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 20., 0., 20.)).count(), 16);
+        assert_eq!(tree.k_nearest(&Point2D::new(10., 10.), 20, Some(1.)).len(), 16);
+        assert_eq!(tree.neighbors(keys[3]).len(), 15);
+
+        // removing from the middle keeps every other element reachable
+        assert_eq!(tree.remove(keys[3]), Some(Point2D::new(10., 10. + 3e-6)));
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 20., 0., 20.)).count(), 15);
+        assert!(tree.insert(Point2D::new(10., 10.)).is_ok());
+
+        for (i, key) in keys.into_iter().enumerate() {
+            if i != 3 {
+                assert!(tree.remove(key).is_some());
+            }
+        }
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 100., 0., 100.)).count(), 2);
+        assert_eq!(tree.remove(other), Some(Point2D::new(80., 80.)));
+        assert_eq!(tree.values(), vec![&Point2D::new(10., 10.)]);
+    }
+
+    #[test]
+    fn test_update() {
+        let mut tree = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let a = tree.insert(Point2D::new(10., 10.)).unwrap();
+        let b = tree.insert(Point2D::new(12., 12.)).unwrap();
+        let c = tree.insert(Point2D::new(90., 90.)).unwrap();
+        let shape = tree.bounds().len();
+
+        // stays within its leaf
+        assert_eq!(tree.update(c, Point2D::new(80., 80.)).unwrap(), Some(Point2D::new(90., 90.)));
+        assert_eq!(tree.bounds().len(), shape);
+
+        // leaves its leaf; the branches that separated it from `b` are removed
+        tree.update(a, Point2D::new(60., 10.)).unwrap();
+        assert_eq!(tree.bounds().len(), 3);
+        assert_eq!(tree.neighbors(a).len(), 2);
+
+        assert!(tree.update(b, Point2D::new(120., 10.)).is_err());
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 20., 0., 20.)).collect::<Vec<_>>(),
+                   vec![&Point2D::new(12., 12.)]);
+
+        let outside = tree.move_all(|_, p| p.x += 30.);
+        assert_eq!(outside, vec![(c, Point2D::new(110., 80.))]);
+        assert_eq!(tree.get(c), None);
+        assert_eq!(tree.k_nearest(&Point2D::new(40., 10.), 1, None),
+                   vec![(&Point2D::new(42., 12.), (4f32 + 4.).sqrt())]);
+    }
+
+    #[test]
+    fn test_auto_grow() {
+        let mut tree = LinearQuadtree::with_auto_grow(Bounds::new(0., 10., 0., 10.));
+        let corner = tree.insert(Point2D::new(0., 0.)).unwrap();
+        tree.insert(Point2D::new(2., 2.)).unwrap();
+        tree.insert(Point2D::new(8., 8.)).unwrap();
+        let far = tree.insert(Point2D::new(25., -5.)).unwrap();
+        assert_eq!(tree.space_boundary(), Bounds::new(0., 40., -30., 10.));
+
+        // keys are re-prefixed, and the point on the old corner
+        // moves next to the old root
+        let mut fresh = LinearQuadtree::new(tree.space_boundary());
+        for p in [[0., 0.], [2., 2.], [8., 8.], [25., -5.]].iter() {
+            fresh.insert(Point2D::new(p[0], p[1])).unwrap();
+        }
+        let shape = tree.bounds();
+        assert_eq!(shape.len(), fresh.bounds().len());
+        assert!(fresh.bounds().iter().all(|b| shape.contains(b)));
+        assert_eq!(tree.neighbors_within(corner, 3.), vec![&Point2D::new(2., 2.)]);
+
+        tree.update(far, Point2D::new(-5., 20.)).unwrap();
+        assert_eq!(tree.space_boundary(), Bounds::new(-40., 40., -30., 50.));
+        assert_eq!(tree.query_bounds(&Bounds::new(-10., 0., 10., 30.)).count(), 1);
+        assert_eq!(tree.remove(far), Some(Point2D::new(-5., 20.)));
+
+        let mut fixed = LinearQuadtree::new(Bounds::new(0., 10., 0., 10.));
+        assert!(fixed.insert(Point2D::new(25., -5.)).is_err());
+    }
+
+    #[test]
+    fn test_grow_overflow() {
+        // sixteen elements fill every overflow key of a node at the deepest
+        // level, and one more lies in the node next to it
+        let mut tree = LinearQuadtree::with_auto_grow(Bounds::new(0., 100., 0., 100.));
+        for _ in 0..16 {
+            tree.insert(Point2D::new(0.01, 0.01)).unwrap();
+        }
+        let next = tree.insert(Point2D::new(0.035, 0.01)).unwrap();
+        let shape = tree.bounds();
+
+        // doubling the boundary merges both nodes, which can not hold them all
+        assert_eq!(tree.insert(Point2D::new(150., 150.)), Err(SpatialError::KeyOverflow));
+        assert_eq!(tree.space_boundary(), Bounds::new(0., 100., 0., 100.));
+        assert_eq!(tree.bounds(), shape);
+        assert_eq!(tree.len(), 17);
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 1., 0., 1.)).count(), 17);
+        assert_eq!(tree.get(next), Some(&Point2D::new(0.035, 0.01)));
+        assert!(tree.insert(Point2D::new(50., 50.)).is_ok());
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let points: Vec<Point2D> = (0..200)
+            .map(|i| Point2D::new((i * 37 % 100) as f32, (i * 11 % 100) as f32))
+            .collect();
+        let mut inserted = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        for p in &points {
+            inserted.insert(*p).unwrap();
+        }
+
+        // loaded on top of elements already in the tree
+        let mut loaded = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let first = loaded.insert(points[0]).unwrap();
+        let keys = loaded.bulk_load(points[1..].iter().copied()).unwrap();
+        assert_eq!(loaded.values().len(), 200);
+        assert_eq!(loaded.bounds().len(), inserted.bounds().len());
+        assert_eq!(loaded.neighbors(first).len(), inserted.neighbors(first).len());
+        assert_eq!(loaded.remove(keys[9]), Some(points[10]));
+
+        // nothing is inserted if a single element does not fit
+        assert!(loaded.bulk_load(vec![Point2D::new(5., 5.), Point2D::new(150., 5.)]).is_err());
+        assert!(loaded.bulk_load(vec![Point2D::new(5., 5.); 17]).is_err());
+        assert_eq!(loaded.values().len(), 199);
+
+        // batches are inserted into the tree built so far
+        let mut extended = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        for batch in points.chunks(20) {
+            extended.extend(batch.iter().copied());
+        }
+        assert_eq!(extended.len(), 200);
+        assert_eq!(extended.bounds().len(), inserted.bounds().len());
+
+        let collected: LinearQuadtree<Point2D> = points.iter().copied().collect();
+        assert_eq!(collected.space_boundary(), Bounds::new(0., 99., 0., 99.));
+        assert_eq!(collected.values().len(), 200);
+    }
+
+    #[test]
+    fn test_iterators() {
+        let mut tree = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let keys: Vec<_> = (0..10)
+            .map(|i| tree.insert(Point2D::new(i as f32 * 10. + 5., 50.)).unwrap())
+            .collect();
+
+        assert_eq!(tree.iter().len(), 10);
+        let mut found: Vec<_> = (&tree).into_iter().collect();
+        found.sort_by_key(|&(key, _)| key);
+        assert_eq!(found[3], (keys[3], &Point2D::new(35., 50.)));
+        let mut found: Vec<_> = tree.keys().collect();
+        found.sort();
+        assert_eq!(found, keys);
+
+        tree.retain(|_, p| p.x < 60.);
+        assert_eq!(tree.len(), 6);
+        assert_eq!(tree.get(keys[7]), None);
+
+        // moved elements are found and placed again, except the ones
+        // that left the space boundary
+        for (key, p) in &mut tree {
+            if key == keys[0] {
+                p.x = 95.;
+            } else if key == keys[1] {
+                p.x = 150.;
+            }
+        }
+        assert_eq!(tree.reindex(), vec![(keys[1], Point2D::new(150., 50.))]);
+        assert_eq!(tree.query_bounds(&Bounds::new(90., 100., 0., 100.)).count(), 1);
+        assert_eq!(tree.k_nearest(&Point2D::new(100., 50.), 1, None)[0], (&Point2D::new(95., 50.), 5.));
+
+        let mut drained: Vec<_> = tree.drain().map(|(_, p)| p.x).collect();
+        drained.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(drained, vec![25., 35., 45., 55., 95.]);
+        assert!(tree.is_empty());
+        assert_eq!(tree.get(keys[0]), None);
+        assert_eq!(tree.bounds().len(), 0);
+
+        tree.insert(Point2D::new(1., 1.)).unwrap();
+        assert_eq!(tree.into_iter().map(|(_, p)| p).collect::<Vec<_>>(), vec![Point2D::new(1., 1.)]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_owned_elements() {
+        let mut tree = LinearQuadtree::with_auto_grow(Bounds::new(0., 100., 0., 100.));
+        let keys = tree.bulk_load((0..20).map(|i| named(&i.to_string(), i as f32 * 5. + 1., 50.))).unwrap();
+        assert_eq!(tree.get(keys[3]).unwrap().name, "3");
+
+        // collisions move the stored element further down, and growing
+        // the tree moves every element to a new key
+        let same = tree.insert(named("same", 36., 50.)).unwrap();
+        let far = tree.insert(named("far", 250., 50.)).unwrap();
+        assert_eq!(tree.get(same).unwrap().name, "same");
+        assert_eq!(tree.get(keys[7]).unwrap().name, "7");
+        assert_eq!(tree.get(far).unwrap().name, "far");
+
+        assert_eq!(tree.update(keys[7], named("seven", 90., 10.)), Ok(Some(named("7", 36., 50.))));
+        assert_eq!(tree.k_nearest(&[90., 10.], 1, None), vec![(&named("seven", 90., 10.), 0.)]);
+        assert_eq!(tree.remove(keys[7]), Some(named("seven", 90., 10.)));
+        assert_eq!(tree.remove(keys[7]), None);
+
+        tree.extend(vec![named("a", 1., 1.), named("b", 1., 1.)]);
+        let mut names: Vec<String> = tree.into_iter().map(|(_, item)| item.name).collect();
+        names.sort();
+        assert_eq!(names.len(), 23);
+        assert_eq!(&names[..3], ["0", "1", "10"]);
+    }
+
+    #[test]
+    fn test_k_nearest() {
+        let mut tree = LinearQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        for i in 0..10 {
+            tree.insert(Point2D::new(i as f32 * 10. + 5., 50.)).unwrap();
+        }
+
+        let found = tree.k_nearest(&Point2D::new(36., 50.), 3, None);
+        assert_eq!(found, vec![
+            (&Point2D::new(35., 50.), 1.),
+            (&Point2D::new(45., 50.), 9.),
+            (&Point2D::new(25., 50.), 11.),
+        ]);
+
+        let found = tree.k_nearest(&Point2D::new(36., 50.), 3, Some(10.));
+        assert_eq!(found.len(), 2);
+
+        assert_eq!(tree.k_nearest(&Point2D::new(36., 50.), 20, None).len(), 10);
+    }
+}
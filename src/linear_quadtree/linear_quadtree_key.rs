@@ -1,5 +1,5 @@
-use crate::core::{Quadrant, Bounds, Result, SpatialError};
-use std::fmt::{Formatter, Error};
+use crate::core::{Quadrant, QUADRANTS, Bounds, Scalar, Result, SpatialError, LinearKey};
+use std::fmt::Formatter;
 
 mod consts {
     /// maximum depth of the tree using these nodes
@@ -19,6 +19,10 @@ mod consts {
         0xAAAAAA, // south
         0xAAAAAB  // south-east
     ];
+    /// column and row offsets of each of the directions above
+    pub const DIRECTIONS: [(i32, i32); 8] = [
+        (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)
+    ];
 }
 
 /// Node used for indexing linear quadtrees
@@ -27,7 +31,7 @@ mod consts {
 /// Based on the paper 'Finding Neighbors of Equal Size
 /// in Linear Quadtrees and Octrees in Constant Time'
 /// by Gunther Shrack (1991)
-#[derive(Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct LinearQuadTreeNode {
     /// [31:28]: overflow | 4-bit unsigned
     ///                   | values: 0-16
@@ -38,13 +42,12 @@ pub struct LinearQuadTreeNode {
     location: u32,
 }
 
-impl Default for LinearQuadTreeNode {
-    fn default() -> Self {
-        Self { location: 0 }
-    }
-}
-
 impl LinearQuadTreeNode {
+    /// Deepest level a key can represent
+    pub const MAX_LEVEL: u32 = consts::RESOLUTION;
+    /// Largest value of the overflow field
+    pub const MAX_OVERFLOW: u32 = 15;
+
     /// Creates new linear quad tree node based on location bits
     /// and level
     pub fn new(coordinate: u32, level: u32) -> LinearQuadTreeNode {
@@ -64,32 +67,73 @@ impl LinearQuadTreeNode {
     /// Returns the keys of the 8 surrounding
     /// quadtree keys of equal level that may or may not exist.
     /// within the linearquadtree instance but can be verified in
-    /// 0(1) time
+    /// 0(1) time. Directions that fall outside of the
+    /// space boundary are none
     pub fn compute_neighbors(&self) -> [Option<LinearQuadTreeNode>; 8] {
         let mut ret = [None; 8];
 
         let location = self.coordinate();
         let level = self.level();
+        let (x, y) = self.grid_position();
 
-        for i in 0..8 {
+        for (i, (&increment, &(dx, dy))) in consts::DIRECTION_INCREMENTS.iter()
+            .zip(consts::DIRECTIONS.iter()).enumerate() {
             let ni = location;
-            let delta_ni = consts::DIRECTION_INCREMENTS[i] <<
+            let delta_ni = increment <<
                 (2 * (consts::RESOLUTION - level));
 
             let mi =
                 (((ni | consts::T_Y) + (delta_ni & consts::T_X)) & consts::T_X) |
                 (((ni | consts::T_X) + (delta_ni & consts::T_Y)) & consts::T_Y);
 
-            ret[i].replace(
-                LinearQuadTreeNode::new(mi, level)
-            );
+            // the increment wraps around at the edge of the space, so
+            // discard anything that did not land at the expected offset
+            let neighbor = LinearQuadTreeNode::new(mi, level);
+            let (nx, ny) = neighbor.grid_position();
+            if level > 0 && nx as i32 - x as i32 == dx && ny as i32 - y as i32 == dy {
+                ret[i].replace(neighbor);
+            }
         }
         ret
     }
 
+    /// Returns the column and row of this key within the grid
+    /// of equally sized nodes at its level
+    pub fn grid_position(&self) -> (u32, u32) {
+        let coordinate = self.coordinate() >> (2 * (consts::RESOLUTION - self.level()));
+        let (mut x, mut y) = (0, 0);
+        for i in 0..self.level() {
+            x |= ((coordinate >> (2 * i)) & 1) << i;
+            y |= ((coordinate >> (2 * i + 1)) & 1) << i;
+        }
+        (x, y)
+    }
+
+    /// Returns true if the regions of the two keys share an edge
+    /// or corner without overlapping. Keys may be of different levels
+    pub fn is_adjacent(&self, other: &LinearQuadTreeNode) -> bool {
+        let ((x_lo, x_hi), (y_lo, y_hi)) = self.finest_range();
+        let ((ox_lo, ox_hi), (oy_lo, oy_hi)) = other.finest_range();
+
+        let overlaps = x_lo <= ox_hi && ox_lo <= x_hi && y_lo <= oy_hi && oy_lo <= y_hi;
+        let touches = x_lo <= ox_hi + 1 && ox_lo <= x_hi + 1 && y_lo <= oy_hi + 1 && oy_lo <= y_hi + 1;
+        touches && !overlaps
+    }
+
+    /// Returns the inclusive column and row ranges covered by this key
+    /// in the grid of the deepest level
+    fn finest_range(&self) -> ((u32, u32), (u32, u32)) {
+        let (x, y) = self.grid_position();
+        let shift = consts::RESOLUTION - self.level();
+        (
+            (x << shift, ((x + 1) << shift) - 1),
+            (y << shift, ((y + 1) << shift) - 1)
+        )
+    }
+
     #[inline(always)]
     pub fn quadrant_at_level(&self, level: u32) -> Quadrant {
-        assert!(level < consts::RESOLUTION);
+        assert!((1..=consts::RESOLUTION).contains(&level));
 
         let location_masked = (self.location >> ((12 - level) * 2)) & 0b11;
         match location_masked {
@@ -139,11 +183,21 @@ impl LinearQuadTreeNode {
         }
     }
 
-    pub fn increment_overflow(&mut self) {
+    /// Moves this key to the next overflow identifier, or returns
+    /// a `KeyOverflow` error once all of them are in use
+    pub fn increment_overflow(&mut self) -> Result<()> {
         let overflow = self.overflow().unwrap_or(0) + 1;
-        assert_ne!(overflow, 16);
+        if overflow > Self::MAX_OVERFLOW {
+            return Err(SpatialError::KeyOverflow);
+        }
         let mask = !(0xF << 28);
-        self.location = (self.location & mask) | overflow;
+        self.location = (self.location & mask) | (overflow << 28);
+        Ok(())
+    }
+
+    /// Returns this key with the overflow field cleared
+    pub fn without_overflow(&self) -> Self {
+        LinearQuadTreeNode::from_location(self.location & !(0xF << 28))
     }
 
     pub fn unit_bounds(&self) -> Bounds {
@@ -156,11 +210,14 @@ impl LinearQuadTreeNode {
 
     /// mutates this key to represent further subdivision
     /// of location based on input quadrant
-    /// (subset of self)
-    pub fn write_level(&mut self, quadrant: Quadrant){
+    /// (subset of self). Returns a `DepthExceeded` error if
+    /// the key is already at the deepest level
+    pub fn write_level(&mut self, quadrant: Quadrant) -> Result<()> {
 
-        let bits = (self.level() + 1);
-        assert!(bits <= 12);
+        let bits = self.level() + 1;
+        if bits > consts::RESOLUTION {
+            return Err(SpatialError::DepthExceeded);
+        }
 
         let mask = !(0xF << 24);
         self.location = (self.location & mask) | (bits << 24);
@@ -174,42 +231,71 @@ impl LinearQuadTreeNode {
 
         let mask = !(0b11 << ((12-self.level()) * 2));
         self.location = (self.location & mask) | bits;
+        Ok(())
     }
 
     /// mutates this key to represent location one level down
     /// (superset of self)
     pub fn remove_level(&mut self) {
         let mask = !(0b11 << ((12-self.level()) * 2));
-        self.location = self.location & mask;
+        self.location &= mask;
 
-        let bits = self.level().checked_sub(1).unwrap_or(0) << 24;
+        let bits = self.level().saturating_sub(1) << 24;
         let mask = !(0xF << 24);
         self.location = (self.location & mask) | bits;
     }
 
     /// returns new key that is one level deeper than self within
     /// certain input quadrant. If resolution limit is reached, function will
-    /// return a QuadtreeKeyOverflowError
-    pub fn child(&self, quadrant: Quadrant) -> crate::core::Result<Self> {
-        if self.overflow().is_some() || self.level() == consts::RESOLUTION {
-            Err(SpatialError::QuadtreeKeyOverflowError)
-        } else {
-            let mut ret = self.clone();
-            ret.write_level(quadrant);
-            Ok(ret)
+    /// return a `DepthExceeded` error
+    pub fn child(&self, quadrant: Quadrant) -> Result<Self> {
+        if self.overflow().is_some() {
+            return Err(SpatialError::DepthExceeded);
         }
+        let mut ret = *self;
+        ret.write_level(quadrant)?;
+        Ok(ret)
+    }
+
+    /// Returns the key of the same node once the space is doubled, with
+    /// the old space taking up `quadrant` of the new one. Returns a
+    /// `DepthExceeded` error if the key is already at the deepest level
+    pub fn wrap(&self, quadrant: Quadrant) -> Result<Self> {
+        if self.level() >= consts::RESOLUTION {
+            return Err(SpatialError::DepthExceeded);
+        }
+        let coordinate = LinearQuadTreeNode::default().child(quadrant)?.coordinate()
+            | (self.coordinate() >> 2);
+        let overflow = self.location & (0xF << 28);
+        Ok(LinearQuadTreeNode::from_location(overflow | ((self.level() + 1) << 24) | coordinate))
+    }
+
+    /// Returns the key of the node at `level` that holds this one.
+    /// Keys at or above `level` are returned without their overflow
+    pub fn ancestor(&self, level: u32) -> Self {
+        let level = level.min(self.level());
+        let mask = (0xFFFFFF << (2 * (consts::RESOLUTION - level))) & 0xFFFFFF;
+        LinearQuadTreeNode::new(self.coordinate() & mask, level)
+    }
+
+    /// Returns the deepest level of a node holding both keys
+    pub fn common_level(&self, other: &LinearQuadTreeNode) -> u32 {
+        let diff = self.coordinate() ^ other.coordinate();
+        let shared = (diff.leading_zeros() - 8) / 2;
+        shared.min(self.level()).min(other.level())
     }
 
     /// Returns new key that is one level above self
     pub fn parent(&self) -> Option<Self> {
         if self.level() == 0 { return None; }
-        let mut ret = self.clone();
+        let mut ret = *self;
         ret.remove_level();
         Some(ret)
     }
 
-    pub fn to_bounds(&self, spatial_bound: &Bounds) -> Bounds {
-        let mut ret = spatial_bound.clone();
+    pub fn to_bounds<S>(&self, spatial_bound: &Bounds<S>) -> Bounds<S>
+        where S: Scalar {
+        let mut ret = *spatial_bound;
         for quadrant in self.coordinate_in_quadrants(){
             ret = ret.sub_bound(quadrant);
         }
@@ -218,6 +304,23 @@ impl LinearQuadTreeNode {
 
 }
 
+impl LinearKey for LinearQuadTreeNode {
+    type Child = Quadrant;
+
+    const CHILDREN: &'static [Quadrant] = &QUADRANTS;
+    const MAX_LEVEL: u32 = LinearQuadTreeNode::MAX_LEVEL;
+
+    fn level(&self) -> u32 { LinearQuadTreeNode::level(self) }
+    fn overflow(&self) -> Option<u32> { LinearQuadTreeNode::overflow(self) }
+    fn increment_overflow(&mut self) -> Result<()> { LinearQuadTreeNode::increment_overflow(self) }
+    fn without_overflow(&self) -> Self { LinearQuadTreeNode::without_overflow(self) }
+    fn child(&self, quadrant: Quadrant) -> Result<Self> { LinearQuadTreeNode::child(self, quadrant) }
+    fn parent(&self) -> Option<Self> { LinearQuadTreeNode::parent(self) }
+    fn child_at_level(&self, level: u32) -> Quadrant { self.quadrant_at_level(level) }
+    fn neighbors(&self) -> Vec<Self> { self.compute_neighbors().iter().flatten().copied().collect() }
+    fn is_adjacent(&self, other: &Self) -> bool { LinearQuadTreeNode::is_adjacent(self, other) }
+}
+
 impl std::fmt::Display for LinearQuadTreeNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let vec = self.coordinate_in_quadrants();
@@ -245,8 +348,8 @@ impl std::fmt::Debug for LinearQuadTreeNode {
 
 #[cfg(test)]
 mod test {
-    use super::LinearQuadTreeNode;
-    use crate::core::Quadrant;
+    use super::{consts, LinearQuadTreeNode};
+    use crate::core::{Quadrant, SpatialError};
 
     #[test]
     fn test_location_level() {
@@ -301,12 +404,49 @@ mod test {
 
     }
 
+    #[test]
+    fn test_quadtree_neighbors_at_edge() {
+        // level 2: BL|BL is the bottom-left corner of the space
+        let node = LinearQuadTreeNode::new(0, 2);
+        assert_eq!(node.grid_position(), (0, 0));
+
+        let neighbors = node.compute_neighbors();
+        assert!(neighbors[0].is_some(), "east");
+        assert!(neighbors[1].is_some(), "north-east");
+        assert!(neighbors[2].is_some(), "north");
+        assert!(neighbors[3..].iter().all(|n| n.is_none()));
+
+        // at level 1 the space is only two nodes wide
+        let node = LinearQuadTreeNode::new(0, 1);
+        assert_eq!(node.compute_neighbors().iter().filter(|n| n.is_some()).count(), 3);
+
+        let root: LinearQuadTreeNode = Default::default();
+        assert!(root.compute_neighbors().iter().all(|n| n.is_none()));
+    }
+
+    #[test]
+    fn test_is_adjacent() {
+        let node: LinearQuadTreeNode = Default::default();
+        let bl = node.child(Quadrant::BL).unwrap();
+        let tr = node.child(Quadrant::TR).unwrap();
+        let br = node.child(Quadrant::BR).unwrap();
+
+        assert!(bl.is_adjacent(&tr));
+        assert!(bl.is_adjacent(&br));
+        assert!(!bl.is_adjacent(&bl));
+        assert!(!bl.is_adjacent(&node));
+
+        // smaller node across the edge of a larger one
+        assert!(bl.is_adjacent(&br.child(Quadrant::TL).unwrap()));
+        assert!(!bl.is_adjacent(&br.child(Quadrant::TR).unwrap()));
+    }
+
     #[test]
     fn test_write_level() {
         let mut node: LinearQuadTreeNode = Default::default();
-        node.write_level(Quadrant::TL);
+        node.write_level(Quadrant::TL).unwrap();
         assert_eq!(node.level(), 1);
-        node.write_level(Quadrant::TR);
+        node.write_level(Quadrant::TR).unwrap();
         assert_eq!(node.level(), 2);
         assert_eq!(node.coordinate_in_quadrants(), 
             vec![Quadrant::TL, Quadrant::TR]);
@@ -327,6 +467,65 @@ mod test {
         assert_eq!(child2.level(), 2);
     }
 
+    #[test]
+    fn test_overflow() {
+        let mut node = LinearQuadTreeNode::new(0b011000000000000000000000, 2);
+        assert_eq!(node.overflow(), None);
+
+        node.increment_overflow().unwrap();
+        node.increment_overflow().unwrap();
+        assert_eq!(node.overflow(), Some(2));
+        assert_eq!(node.level(), 2);
+        assert_eq!(node.coordinate(), 0b011000000000000000000000);
+
+        assert_eq!(node.without_overflow(), LinearQuadTreeNode::new(0b011000000000000000000000, 2));
+
+        while node.overflow() != Some(LinearQuadTreeNode::MAX_OVERFLOW) {
+            node.increment_overflow().unwrap();
+        }
+        assert_eq!(node.increment_overflow(), Err(SpatialError::KeyOverflow));
+        assert_eq!(node.overflow(), Some(LinearQuadTreeNode::MAX_OVERFLOW));
+    }
+
+    #[test]
+    fn test_depth_exceeded() {
+        let mut node: LinearQuadTreeNode = Default::default();
+        for _ in 0..consts::RESOLUTION {
+            node = node.child(Quadrant::TR).unwrap();
+        }
+        assert_eq!(node.child(Quadrant::TR), Err(SpatialError::DepthExceeded));
+        assert_eq!(node.write_level(Quadrant::TR), Err(SpatialError::DepthExceeded));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let node = LinearQuadTreeNode::new(0b011000000000000000000000, 2);
+        let wrapped = node.wrap(Quadrant::TR).unwrap();
+        assert_eq!(wrapped.level(), 3);
+        assert_eq!(wrapped.coordinate_in_quadrants(),
+                   vec![Quadrant::TR, Quadrant::BR, Quadrant::TL]);
+
+        let root: LinearQuadTreeNode = Default::default();
+        assert_eq!(root.wrap(Quadrant::BL), root.child(Quadrant::BL));
+
+        let mut node = LinearQuadTreeNode::default();
+        for _ in 0..consts::RESOLUTION {
+            node = node.child(Quadrant::BL).unwrap();
+        }
+        assert_eq!(node.wrap(Quadrant::BL), Err(SpatialError::DepthExceeded));
+    }
+
+    #[test]
+    fn test_common_level() {
+        let node = LinearQuadTreeNode::new(0b011011000000000000000000, 3);
+        let sibling = LinearQuadTreeNode::new(0b011000000000000000000000, 3);
+        assert_eq!(node.common_level(&sibling), 2);
+        assert_eq!(node.ancestor(2), sibling.parent().unwrap());
+        assert_eq!(node.common_level(&node), 3);
+        assert_eq!(node.common_level(&node.ancestor(1)), 1);
+        assert_eq!(node.ancestor(0), LinearQuadTreeNode::default());
+    }
+
     #[test]
     fn test_parent_child() {
         let node: LinearQuadTreeNode = Default::default();
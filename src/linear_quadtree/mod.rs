@@ -1,5 +1,11 @@
 mod linear_quadtree_key;
+#[allow(clippy::module_inception)]
 mod linear_quadtree;
 
 pub use linear_quadtree_key::LinearQuadTreeNode as Key;
-pub use linear_quadtree::LinearQuadtree as LinearQuadtree;
\ No newline at end of file
+pub use linear_quadtree::LinearQuadtree as LinearQuadtree;
+pub use linear_quadtree::QueryBounds as QueryBounds;
+pub use linear_quadtree::Iter as Iter;
+pub use linear_quadtree::IterMut as IterMut;
+pub use linear_quadtree::Drain as Drain;
+pub use linear_quadtree::IntoIter as IntoIter;
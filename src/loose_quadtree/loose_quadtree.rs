@@ -0,0 +1,349 @@
+use std::iter;
+use std::mem;
+use std::slice;
+use slotmap::DenseSlotMap;
+use slotmap::dense::Values;
+use crate::core::{Spatial2D, SpatialBounds2D, SpatialKey, AreaNode, Bounds, Scalar, Result, SpatialError};
+
+const MAX_RECURCION: u32 = 8;
+
+/// Quadtree over moving objects covering an area. Every node accepts
+/// objects centered inside its bounds that fit within its bounds
+/// stretched by the looseness factor, so the depth of an object only
+/// depends on its size
+#[derive(Debug)]
+pub struct LooseQuadtree<T>
+    where T: SpatialBounds2D {
+    container: DenseSlotMap<SpatialKey, T>,
+    root: AreaNode,
+    looseness: f32,
+    pub bounds: Bounds<T::Scalar>
+}
+
+impl<T> LooseQuadtree<T>
+    where T: SpatialBounds2D {
+    /// Creates a tree whose nodes are stretched to twice their size
+    pub fn new(bounds: Bounds<T::Scalar>) -> Self {
+        LooseQuadtree::with_looseness(bounds, 2.)
+    }
+
+    /// Creates a tree whose nodes are stretched to `looseness` times
+    /// their size. Panics if `looseness` is not above 1
+    pub fn with_looseness(bounds: Bounds<T::Scalar>, looseness: f32) -> Self {
+        assert!(looseness > 1., "looseness must be above 1");
+        LooseQuadtree {
+            container: DenseSlotMap::with_key(),
+            root: AreaNode::default(),
+            looseness,
+            bounds
+        }
+    }
+}
+
+impl<T> LooseQuadtree<T>
+    where T: SpatialBounds2D {
+
+    /// Inserts the object and returns a key that stays valid until it is
+    /// removed. Fails if the center of the object is outside the tree or
+    /// the object is too large for the stretched root
+    pub fn insert(&mut self, item: T) -> Result<SpatialKey> {
+        let (center, depth) = self.locate(item.bounds())?;
+        let key = self.container.insert(item);
+        self.root.node_at_mut(center, depth, self.bounds).items.push(key);
+        Ok(key)
+    }
+
+    pub fn get(&self, key: SpatialKey) -> Option<&T> {
+        self.container.get(key)
+    }
+
+    pub fn contains_key(&self, key: SpatialKey) -> bool {
+        self.container.contains_key(key)
+    }
+
+    /// Removes the object stored under `key` and returns it
+    pub fn remove(&mut self, key: SpatialKey) -> Option<T> {
+        let item = self.container.remove(key)?;
+        if let Ok((center, depth)) = self.locate(item.bounds()) {
+            self.root.remove_at(key, center, depth, self.bounds);
+        }
+        Some(item)
+    }
+
+    /// Replaces the object stored under `key`. The tree is only changed if
+    /// the object moved into another node. Returns the previous object,
+    /// or none if the key is not in the tree
+    pub fn update(&mut self, key: SpatialKey, item: T) -> Result<Option<T>> {
+        let old_bounds = match self.container.get(key) {
+            Some(old) => old.bounds(),
+            None => return Ok(None)
+        };
+        let (center, depth) = self.locate(item.bounds())?;
+        if let Ok((old_center, old_depth)) = self.locate(old_bounds) {
+            if old_depth != depth || !same_node(old_center, center, depth, self.bounds) {
+                self.root.remove_at(key, old_center, old_depth, self.bounds);
+                self.root.node_at_mut(center, depth, self.bounds).items.push(key);
+            }
+        }
+        Ok(Some(mem::replace(&mut self.container[key], item)))
+    }
+
+    pub fn values(&self) -> Values<'_, SpatialKey, T> {
+        self.container.values()
+    }
+
+    /// Returns the bounds of every node in the tree, before stretching
+    pub fn bounds(&self) -> Vec<Bounds<T::Scalar>> {
+        let mut vec = vec![];
+        if !self.root.is_empty() {
+            self.root.bounds(&mut vec, self.bounds);
+        }
+        vec
+    }
+
+    /// Returns an iterator over the objects whose bounds contain `p`
+    pub fn query_point(&self, p: &dyn Spatial2D<Scalar = T::Scalar>) -> QueryBounds<'_, T> {
+        let (x, y) = p.pos();
+        self.query_bounds(&Bounds::new(x, x, y, y))
+    }
+
+    /// Returns an iterator over the objects whose bounds intersect the
+    /// query region, including objects that only touch its edge
+    pub fn query_bounds(&self, query: &Bounds<T::Scalar>) -> QueryBounds<'_, T> {
+        let mut stack = vec![];
+        if loosen(self.bounds, self.looseness).intersects(*query) {
+            stack.push((&self.root, self.bounds));
+        }
+        QueryBounds {
+            container: &self.container,
+            query: *query,
+            looseness: self.looseness,
+            stack,
+            pending: [].iter()
+        }
+    }
+
+    /// Returns the other objects sharing an area with the object stored
+    /// under `key`. Objects that only touch it are not returned
+    pub fn overlapping(&self, key: SpatialKey) -> Vec<(SpatialKey, &T)> {
+        let item_bounds = match self.container.get(key) {
+            Some(item) => item.bounds(),
+            None => return vec![]
+        };
+        let mut query = self.query_bounds(&item_bounds);
+        iter::from_fn(|| query.next_entry())
+            .filter(|&(other, item)| other != key && item.bounds().overlaps(item_bounds))
+            .collect()
+    }
+
+    /// Returns the center of the object and the depth of the node it
+    /// belongs in. A stretched node at depth `d` holds objects up to
+    /// `(looseness - 1) / 2^d` times the size of the root
+    fn locate(&self, item_bounds: Bounds<T::Scalar>) -> Result<([T::Scalar; 2], u32)> {
+        let (x, y) = item_bounds.center();
+        let center = [x, y];
+        if !self.bounds.contains_point(&center) {
+            return Err(SpatialError::out_of_bounds((x, y), self.bounds));
+        }
+        let fit = |extent: T::Scalar, size: T::Scalar| {
+            if extent > T::Scalar::ZERO {
+                ((self.looseness as f64 - 1.) * size.to_f64() / extent.to_f64()).log2().floor()
+            } else {
+                MAX_RECURCION as f64
+            }
+        };
+        let depth = fit(item_bounds.width(), self.bounds.width())
+            .min(fit(item_bounds.height(), self.bounds.height()));
+        if depth < 0. {
+            let root = loosen(self.bounds, self.looseness);
+            return Err(SpatialError::region_out_of_bounds(item_bounds, root));
+        }
+        Ok((center, (depth as u32).min(MAX_RECURCION)))
+    }
+}
+
+/// Returns the bounds of a node stretched by the looseness factor
+fn loosen<S>(bounds: Bounds<S>, looseness: f32) -> Bounds<S>
+    where S: Scalar {
+    let grow = |extent: S| S::from_f64(extent.to_f64() * (looseness as f64 - 1.) / 2.);
+    let grow_x = grow(bounds.width());
+    let grow_y = grow(bounds.height());
+    Bounds::new(
+        bounds.x_min - grow_x,
+        bounds.x_max + grow_x,
+        bounds.y_min - grow_y,
+        bounds.y_max + grow_y,
+    )
+}
+
+/// Returns true if both centers lead to the same node at `depth`
+fn same_node<S>(a: [S; 2], b: [S; 2], depth: u32, mut curr_bound: Bounds<S>) -> bool
+    where S: Scalar {
+    for _ in 0..depth {
+        let quadrant = curr_bound.find_quadrant(&a);
+        if quadrant != curr_bound.find_quadrant(&b) {
+            return false;
+        }
+        curr_bound = curr_bound.sub_bound(quadrant);
+    }
+    true
+}
+
+/// Iterator over the objects intersecting a query region, created by
+/// [`LooseQuadtree::query_bounds`] and [`LooseQuadtree::query_point`]
+pub struct QueryBounds<'a, T>
+    where T: SpatialBounds2D {
+    container: &'a DenseSlotMap<SpatialKey, T>,
+    query: Bounds<T::Scalar>,
+    looseness: f32,
+    stack: Vec<(&'a AreaNode, Bounds<T::Scalar>)>,
+    pending: slice::Iter<'a, SpatialKey>,
+}
+
+impl<'a, T> QueryBounds<'a, T>
+    where T: SpatialBounds2D {
+    fn next_entry(&mut self) -> Option<(SpatialKey, &'a T)> {
+        loop {
+            for key in &mut self.pending {
+                let item = &self.container[*key];
+                if item.bounds().intersects(self.query) {
+                    return Some((*key, item));
+                }
+            }
+            let (node, curr_bound) = self.stack.pop()?;
+            self.pending = node.items.iter();
+            if let Some(branch) = &node.children {
+                for &(child, quadrant) in branch.children().iter() {
+                    let child_bound = curr_bound.sub_bound(quadrant);
+                    if !child.is_empty() && loosen(child_bound, self.looseness).intersects(self.query) {
+                        self.stack.push((child, child_bound));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for QueryBounds<'a, T>
+    where T: SpatialBounds2D {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().map(|(_, item)| item)
+    }
+}
+
+impl AreaNode {
+    /// Returns the node at `depth` holding `center`, creating the nodes
+    /// on the way down
+    fn node_at_mut<S>(&mut self, center: [S; 2], depth: u32, curr_bound: Bounds<S>) -> &mut AreaNode
+        where S: Scalar {
+        if depth == 0 {
+            return self;
+        }
+        let quadrant = curr_bound.find_quadrant(&center);
+        self.children
+            .get_or_insert_with(Default::default)
+            .child_mut(quadrant)
+            .node_at_mut(center, depth - 1, curr_bound.sub_bound(quadrant))
+    }
+
+    /// Removes `key` from the node at `depth` holding `center`, dropping
+    /// children on the way back up once they hold nothing
+    fn remove_at<S>(&mut self, key: SpatialKey, center: [S; 2], depth: u32, curr_bound: Bounds<S>) -> bool
+        where S: Scalar {
+        let removed = if depth == 0 {
+            self.remove_item(key)
+        } else if let Some(branch) = &mut self.children {
+            let quadrant = curr_bound.find_quadrant(&center);
+            branch.child_mut(quadrant).remove_at(key, center, depth - 1, curr_bound.sub_bound(quadrant))
+        } else {
+            false
+        };
+        if removed {
+            self.prune();
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LooseQuadtree;
+    use crate::core::{Bounds, Point2D, SpatialBounds2D};
+
+    #[test]
+    fn test_insert() {
+        let mut tree = LooseQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        // placed by size alone, even though it crosses the center
+        let a = tree.insert(Bounds::new(45., 55., 45., 55.)).unwrap();
+        assert_eq!(tree.bounds().len(), 4);
+
+        // too large for the stretched root
+        assert!(tree.insert(Bounds::new(-10., 100., 0., 10.)).is_err());
+        assert!(tree.insert(Bounds::new(101., 102., 0., 10.)).is_err());
+        // centered inside, so it may stick out of the tree
+        assert!(tree.insert(Bounds::new(-10., 10., 0., 10.)).is_ok());
+
+        // small moves inside the same node keep the tree as it is
+        let shape = tree.bounds();
+        tree.update(a, Bounds::new(42., 52., 44., 54.)).unwrap();
+        assert_eq!(tree.bounds(), shape);
+
+        tree.update(a, Bounds::new(80., 90., 80., 90.)).unwrap();
+        assert_eq!(tree.get(a), Some(&Bounds::new(80., 90., 80., 90.)));
+        assert!(tree.update(a, Bounds::new(120., 130., 0., 10.)).is_err());
+
+        assert_eq!(tree.remove(a), Some(Bounds::new(80., 90., 80., 90.)));
+        assert_eq!(tree.bounds().len(), 3);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Sprite {
+        name: String,
+        area: Bounds,
+    }
+
+    impl SpatialBounds2D for Sprite {
+        type Scalar = f32;
+        fn bounds(&self) -> Bounds {self.area}
+    }
+
+    #[test]
+    fn test_owned_items() {
+        let mut tree = LooseQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let sprite = |name: &str, area| Sprite { name: name.to_string(), area };
+        let a = tree.insert(sprite("player", Bounds::new(10., 14., 10., 14.))).unwrap();
+        tree.insert(sprite("wall", Bounds::new(40., 60., 0., 10.))).unwrap();
+
+        let old = tree.update(a, sprite("player", Bounds::new(12., 16., 10., 14.))).unwrap();
+        assert_eq!(old, Some(sprite("player", Bounds::new(10., 14., 10., 14.))));
+        tree.update(a, sprite("player", Bounds::new(50., 54., 5., 9.))).unwrap();
+        let mut found: Vec<&str> = tree.query_point(&Point2D::new(52., 7.)).map(|s| s.name.as_str()).collect();
+        found.sort();
+        assert_eq!(found, vec!["player", "wall"]);
+        assert_eq!(tree.remove(a).map(|s| s.area), Some(Bounds::new(50., 54., 5., 9.)));
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut tree = LooseQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let a = tree.insert(Bounds::new(40., 60., 40., 60.)).unwrap();
+        let b = tree.insert(Bounds::new(10., 45., 10., 45.)).unwrap();
+        let c = tree.insert(Bounds::new(60., 70., 60., 70.)).unwrap();
+        tree.insert(Bounds::new(80., 90., 10., 20.)).unwrap();
+
+        let mut found: Vec<_> = tree.query_point(&Point2D::new(42., 42.)).cloned().collect();
+        found.sort_by(|l, r| l.x_min.partial_cmp(&r.x_min).unwrap());
+        assert_eq!(found, vec![Bounds::new(10., 45., 10., 45.), Bounds::new(40., 60., 40., 60.)]);
+        assert_eq!(tree.query_point(&Point2D::new(95., 95.)).count(), 0);
+
+        assert_eq!(tree.query_bounds(&Bounds::new(75., 95., 0., 50.)).count(), 1);
+        assert_eq!(tree.query_bounds(&Bounds::new(70., 75., 65., 75.)).count(), 1);
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 100., 0., 100.)).count(), 4);
+
+        let overlapping: Vec<_> = tree.overlapping(a).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(overlapping, vec![b]);
+        assert!(tree.overlapping(c).is_empty());
+    }
+}
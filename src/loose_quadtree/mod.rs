@@ -0,0 +1,5 @@
+#[allow(clippy::module_inception)]
+mod loose_quadtree;
+
+pub use self::loose_quadtree::LooseQuadtree as LooseQuadtree;
+pub use self::loose_quadtree::QueryBounds as QueryBounds;
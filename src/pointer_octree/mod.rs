@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+mod pointer_octree;
+
+pub use self::pointer_octree::PointerOctree as PointerOctree;
+pub use self::pointer_octree::PointerOctreeBuilder as PointerOctreeBuilder;
+pub use self::pointer_octree::QueryBounds as QueryBounds;
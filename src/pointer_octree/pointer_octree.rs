@@ -0,0 +1,575 @@
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+use std::mem;
+use std::slice;
+use slotmap::DenseSlotMap;
+use slotmap::dense::Values;
+use crate::core::{Spatial3D, SpatialKey, Bounds3D, Scalar, OCTANTS, Candidate, CandidateEntry, Result, SpatialError};
+
+const MAX_RECURCION: u32 = 8;
+
+/// Octree over points in 3D space, the counterpart of
+/// [`PointerQuadtree`](crate::pointer_quadtree::PointerQuadtree)
+#[derive(Debug)]
+pub struct PointerOctree<T>
+    where T: Spatial3D {
+    container: DenseSlotMap<SpatialKey, T>,
+    root: OctreeNode,
+    config: OctreeConfig<T::Scalar>,
+    pub bounds: Bounds3D<T::Scalar>
+}
+
+/// Limits on how far nodes of the tree are subdivided
+#[derive(Clone, Copy, Debug)]
+struct OctreeConfig<S> {
+    max_depth: u32,
+    leaf_capacity: usize,
+    min_size: S,
+}
+
+impl<S> OctreeConfig<S>
+    where S: Scalar {
+    /// Returns true if a node with these bounds at this depth
+    /// may be split into eight children
+    fn can_split(&self, bounds: Bounds3D<S>, r_lvl: u32) -> bool {
+        r_lvl < self.max_depth &&
+        bounds.width().half() >= self.min_size &&
+        bounds.height().half() >= self.min_size &&
+        bounds.depth().half() >= self.min_size
+    }
+}
+
+impl<S> Default for OctreeConfig<S>
+    where S: Scalar {
+    fn default() -> Self {
+        OctreeConfig {
+            max_depth: MAX_RECURCION,
+            leaf_capacity: 1,
+            min_size: S::ZERO,
+        }
+    }
+}
+
+impl<T> PointerOctree<T>
+    where T: Spatial3D {
+    pub fn new(bounds: Bounds3D<T::Scalar>) -> Self {
+        PointerOctree::builder(bounds).build()
+    }
+
+    /// Returns a builder for a tree with custom node limits
+    pub fn builder(bounds: Bounds3D<T::Scalar>) -> PointerOctreeBuilder<T> {
+        PointerOctreeBuilder {
+            bounds,
+            config: OctreeConfig::default(),
+            _phantom_data: PhantomData
+        }
+    }
+}
+
+/// Builds a [`PointerOctree`] with custom node limits
+#[derive(Debug)]
+pub struct PointerOctreeBuilder<T>
+    where T: Spatial3D {
+    bounds: Bounds3D<T::Scalar>,
+    config: OctreeConfig<T::Scalar>,
+    _phantom_data: PhantomData<T>
+}
+
+impl<T> PointerOctreeBuilder<T>
+    where T: Spatial3D {
+    /// Sets the deepest level nodes are split to. Leaves at this
+    /// depth hold any number of items. Defaults to 8
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.config.max_depth = max_depth;
+        self
+    }
+
+    /// Sets how many items a leaf holds before it is split.
+    /// Defaults to 1
+    pub fn leaf_capacity(mut self, leaf_capacity: usize) -> Self {
+        self.config.leaf_capacity = leaf_capacity.max(1);
+        self
+    }
+
+    /// Sets the smallest width, height or depth a node may be split
+    /// into. Defaults to 0
+    pub fn min_size(mut self, min_size: T::Scalar) -> Self {
+        self.config.min_size = min_size;
+        self
+    }
+
+    pub fn build(self) -> PointerOctree<T> {
+        PointerOctree {
+            container: DenseSlotMap::with_key(),
+            root: OctreeNode::Empty,
+            config: self.config,
+            bounds: self.bounds
+        }
+    }
+}
+
+impl<T> PointerOctree<T>
+    where T: Spatial3D + PartialEq {
+
+    /// Inserts the item and returns a key that stays valid until it is
+    /// removed, or an `OutOfBounds` error if it lies outside of the tree bounds
+    pub fn insert(&mut self, data: T) -> Result<SpatialKey> {
+        if !self.bounds.contains_point(&data) {
+            return Err(SpatialError::out_of_bounds_3d(data.loc(), self.bounds));
+        }
+        let key = self.container.insert(data);
+        self.root.insert(key, self.bounds, &self.container, &self.config, 0);
+        Ok(key)
+    }
+
+    pub fn get(&self, key: SpatialKey) -> Option<&T> {
+        self.container.get(key)
+    }
+
+    pub fn contains_key(&self, key: SpatialKey) -> bool {
+        self.container.contains_key(key)
+    }
+
+    /// Removes the item at `key` from the tree and returns it
+    pub fn remove_by_key(&mut self, key: SpatialKey) -> Option<T> {
+        let item = self.container.get(key)?;
+        self.root.remove(item, |k, _| k == key, &self.container, self.bounds, &self.config);
+        self.container.remove(key)
+    }
+
+    /// Removes one item equal to `p` from the tree and returns it
+    pub fn remove(&mut self, p: &T) -> Option<T> {
+        if !self.bounds.contains_point(p) {
+            return None;
+        }
+        let key = self.root.remove(p, |_, item| item == p, &self.container, self.bounds, &self.config)?;
+        self.container.remove(key)
+    }
+
+    pub fn contains(&self, p: &T) -> bool {
+        if !self.bounds.contains_point(p) {
+            false
+        } else {
+            self.root.contains(p, &self.container, self.bounds)
+        }
+    }
+
+    pub fn bounds(&self) -> Vec<Bounds3D<T::Scalar>> {
+        let mut vec = vec![];
+        self.root.bounds(&mut vec, self.bounds);
+        vec
+    }
+
+    /// Returns every item whose distance to `p` is at most `radius`
+    pub fn within(&self, p: &dyn Spatial3D<Scalar = T::Scalar>, radius: T::Scalar) -> Vec<&T> {
+        let mut vec = vec![];
+        self.root.within(p, radius, &self.container, self.bounds, &mut vec);
+        vec
+    }
+
+    /// Returns an iterator over every item inside `query`, visiting
+    /// only the nodes whose bounds intersect it
+    pub fn query_bounds(&self, query: &Bounds3D<T::Scalar>) -> QueryBounds<'_, T> {
+        let mut stack = vec![];
+        if self.bounds.intersects(*query) {
+            stack.push((&self.root, self.bounds));
+        }
+        QueryBounds {
+            container: &self.container,
+            query: *query,
+            stack,
+            pending: [].iter()
+        }
+    }
+
+    /// Returns the item closest to `p`, skipping any stored item equal to `p`
+    pub fn closest(&self, p: &T) -> Option<&T> {
+        self.nearest(p)
+            .map(|(key, _)| &self.container[key])
+            .find(|item| *item != p)
+    }
+
+    /// Returns up to `k` items closest to `p` along with their distances,
+    /// sorted from nearest to farthest. If `max_dist` is given, items
+    /// farther away than it are not returned
+    pub fn k_nearest(&self, p: &dyn Spatial3D<Scalar = T::Scalar>, k: usize, max_dist: Option<T::Scalar>) -> Vec<(&T, T::Scalar)> {
+        self.nearest(p)
+            .take_while(|&(_, squared)| match max_dist {
+                Some(max) => T::Scalar::within_radius(squared, max),
+                None => true
+            })
+            .take(k)
+            .map(|(key, squared)| (&self.container[key], T::Scalar::from_squared(squared)))
+            .collect()
+    }
+
+    /// Returns an iterator over stored keys in order of increasing distance to `p`
+    fn nearest(&self, p: &dyn Spatial3D<Scalar = T::Scalar>) -> Nearest<'_, T> {
+        let point = [p.x(), p.y(), p.z()];
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate::node(
+            self.bounds.squared_distance_to_point(&point),
+            (&self.root, self.bounds)
+        ));
+        Nearest {
+            container: &self.container,
+            point,
+            queue
+        }
+    }
+
+    pub fn values(&self) -> Values<'_, SpatialKey, T> {
+        self.container.values()
+    }
+}
+
+/// Iterator over the items inside a query region, created by
+/// [`PointerOctree::query_bounds`]
+pub struct QueryBounds<'a, T>
+    where T: Spatial3D {
+    container: &'a DenseSlotMap<SpatialKey, T>,
+    query: Bounds3D<T::Scalar>,
+    stack: Vec<(&'a OctreeNode, Bounds3D<T::Scalar>)>,
+    pending: slice::Iter<'a, SpatialKey>,
+}
+
+impl<'a, T> Iterator for QueryBounds<'a, T>
+    where T: Spatial3D {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for key in &mut self.pending {
+                let item = &self.container[*key];
+                if self.query.contains_point(item) {
+                    return Some(item);
+                }
+            }
+            let (node, curr_bound) = self.stack.pop()?;
+            match node {
+                OctreeNode::Leaf(keys) | OctreeNode::Saturated(keys) => self.pending = keys.iter(),
+                OctreeNode::Branch(children) => {
+                    for (child, octant) in children.iter().zip(OCTANTS.iter()) {
+                        let child_bound = curr_bound.sub_bound(*octant);
+                        if child_bound.intersects(self.query) {
+                            self.stack.push((child, child_bound));
+                        }
+                    }
+                }
+                OctreeNode::Empty => ()
+            }
+        }
+    }
+}
+
+type NearestCandidate<'a, T> = Candidate<
+    (&'a OctreeNode, Bounds3D<<T as Spatial3D>::Scalar>),
+    SpatialKey,
+    <<T as Spatial3D>::Scalar as Scalar>::Squared
+>;
+
+/// Best-first traversal yielding keys and their squared distances
+/// by increasing distance to a point
+struct Nearest<'a, T>
+    where T: Spatial3D {
+    container: &'a DenseSlotMap<SpatialKey, T>,
+    point: [T::Scalar; 3],
+    queue: BinaryHeap<NearestCandidate<'a, T>>,
+}
+
+impl<'a, T> Iterator for Nearest<'a, T>
+    where T: Spatial3D {
+    type Item = (SpatialKey, <T::Scalar as Scalar>::Squared);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Candidate { distance, entry }) = self.queue.pop() {
+            match entry {
+                CandidateEntry::Item(key) => return Some((key, distance)),
+                CandidateEntry::Node((node, curr_bound)) => match node {
+                    OctreeNode::Leaf(keys) | OctreeNode::Saturated(keys) => {
+                        for &key in keys {
+                            let squared = self.container[key].squared_distance_to(&self.point);
+                            self.queue.push(Candidate::item(squared, key));
+                        }
+                    }
+                    OctreeNode::Branch(children) => {
+                        for (child, octant) in children.iter().zip(OCTANTS.iter()) {
+                            let child_bound = curr_bound.sub_bound(*octant);
+                            self.queue.push(Candidate::node(
+                                child_bound.squared_distance_to_point(&self.point),
+                                (child, child_bound)
+                            ));
+                        }
+                    }
+                    OctreeNode::Empty => ()
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+enum OctreeNode {
+    /// node at the depth or size limit holding more items than
+    /// the leaf capacity
+    Saturated(Vec<SpatialKey>),
+    /// children indexed by `Octant as usize`
+    Branch(Box<[OctreeNode; 8]>),
+    /// node holding up to the leaf capacity of items
+    Leaf(Vec<SpatialKey>),
+    #[default]
+    Empty,
+}
+
+impl OctreeNode {
+    fn insert<T>(
+        &mut self,
+        key: SpatialKey,
+        bounds: Bounds3D<T::Scalar>,
+        container: &DenseSlotMap<SpatialKey, T>,
+        config: &OctreeConfig<T::Scalar>,
+        r_lvl: u32
+    ) where T: Spatial3D {
+        match self {
+            OctreeNode::Saturated(vec) => {
+                vec.push(key);
+            }
+            OctreeNode::Branch(children) => {
+                let octant = bounds.find_octant(&container[key]);
+                children[octant as usize].insert(key, bounds.sub_bound(octant), container, config, r_lvl + 1);
+            }
+            OctreeNode::Leaf(vec) => {
+                if vec.len() < config.leaf_capacity {
+                    vec.push(key);
+                } else if !config.can_split(bounds, r_lvl) {
+                    let mut keys = mem::take(vec);
+                    keys.push(key);
+                    *self = OctreeNode::Saturated(keys);
+                } else {
+                    let keys = mem::take(vec);
+                    *self = OctreeNode::Branch(Box::default());
+                    for other_key in keys.into_iter().chain(Some(key)) {
+                        self.insert(other_key, bounds, container, config, r_lvl);
+                    }
+                }
+            }
+            OctreeNode::Empty => {
+                *self = OctreeNode::Leaf(vec![key]);
+            }
+        }
+    }
+
+    fn contains<T>(
+        &self,
+        p: &T,
+        container: &DenseSlotMap<SpatialKey, T>,
+        curr_bound: Bounds3D<T::Scalar>
+    ) -> bool where T: Spatial3D + PartialEq {
+        match self {
+            OctreeNode::Leaf(vec) | OctreeNode::Saturated(vec) => {
+                vec.iter().any(|key| container[*key] == *p)
+            }
+            OctreeNode::Branch(children) => {
+                let octant = curr_bound.find_octant(p);
+                children[octant as usize].contains(p, container, curr_bound.sub_bound(octant))
+            }
+            OctreeNode::Empty => false
+        }
+    }
+
+    /// Removes the first key in the node holding `p` that matches
+    /// `is_target`, collapsing branches on the way back up
+    fn remove<T, F>(
+        &mut self,
+        p: &T,
+        is_target: F,
+        container: &DenseSlotMap<SpatialKey, T>,
+        curr_bound: Bounds3D<T::Scalar>,
+        config: &OctreeConfig<T::Scalar>
+    ) -> Option<SpatialKey>
+        where T: Spatial3D, F: Fn(SpatialKey, &T) -> bool {
+        match self {
+            OctreeNode::Leaf(vec) | OctreeNode::Saturated(vec) => {
+                let idx = vec.iter().position(|key| is_target(*key, &container[*key]))?;
+                let key = vec.remove(idx);
+                self.shrink(config);
+                Some(key)
+            }
+            OctreeNode::Branch(children) => {
+                let octant = curr_bound.find_octant(p);
+                let key = children[octant as usize]
+                    .remove(p, is_target, container, curr_bound.sub_bound(octant), config)?;
+                self.collapse(config);
+                Some(key)
+            }
+            OctreeNode::Empty => None
+        }
+    }
+
+    /// Turns a branch back into a single leaf once its children hold
+    /// no more items than a leaf can
+    fn collapse<S>(&mut self, config: &OctreeConfig<S>) {
+        if let OctreeNode::Branch(children) = self {
+            let mut keys = vec![];
+            for child in children.iter() {
+                match child {
+                    OctreeNode::Empty => (),
+                    OctreeNode::Leaf(vec) => keys.extend_from_slice(vec),
+                    // deeper structure is still needed
+                    _ => return
+                }
+            }
+            if keys.is_empty() {
+                *self = OctreeNode::Empty;
+            } else if keys.len() <= config.leaf_capacity {
+                *self = OctreeNode::Leaf(keys);
+            }
+        }
+    }
+
+    /// Turns a leaf or saturated node that has lost items into the
+    /// simplest node able to hold the rest
+    fn shrink<S>(&mut self, config: &OctreeConfig<S>) {
+        match self {
+            OctreeNode::Leaf(vec) | OctreeNode::Saturated(vec) if vec.is_empty() => {
+                *self = OctreeNode::Empty;
+            }
+            OctreeNode::Saturated(vec) if vec.len() <= config.leaf_capacity => {
+                *self = OctreeNode::Leaf(mem::take(vec));
+            }
+            _ => ()
+        }
+    }
+
+    fn within<'a, T>(
+        &self,
+        p: &dyn Spatial3D<Scalar = T::Scalar>,
+        radius: T::Scalar,
+        container: &'a DenseSlotMap<SpatialKey, T>,
+        curr_bound: Bounds3D<T::Scalar>,
+        vec: &mut Vec<&'a T>
+    ) where T: Spatial3D {
+        // no part of this node can hold a point inside the sphere
+        if !T::Scalar::within_radius(curr_bound.squared_distance_to_point(p), radius) {
+            return;
+        }
+        match self {
+            OctreeNode::Leaf(keys) | OctreeNode::Saturated(keys) => {
+                for key in keys {
+                    if T::Scalar::within_radius(container[*key].squared_distance_to(p), radius) {
+                        vec.push(&container[*key]);
+                    }
+                }
+            }
+            OctreeNode::Branch(children) => {
+                for (child, octant) in children.iter().zip(OCTANTS.iter()) {
+                    child.within(p, radius, container, curr_bound.sub_bound(*octant), vec);
+                }
+            }
+            OctreeNode::Empty => ()
+        }
+    }
+
+    fn bounds<S>(&self, vec: &mut Vec<Bounds3D<S>>, curr_bound: Bounds3D<S>)
+        where S: Scalar {
+        match self {
+            OctreeNode::Leaf(_) | OctreeNode::Saturated(_) => vec.push(curr_bound),
+            OctreeNode::Branch(children) => {
+                vec.push(curr_bound);
+                for (child, octant) in children.iter().zip(OCTANTS.iter()) {
+                    child.bounds(vec, curr_bound.sub_bound(*octant));
+                }
+            }
+            OctreeNode::Empty => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PointerOctree;
+    use crate::core::{Bounds3D, Point3D, Spatial3D};
+
+    #[test]
+    fn test_insert_remove() {
+        let mut tree = PointerOctree::new(Bounds3D::new(0., 100., 0., 100., 0., 100.));
+        let a = tree.insert(Point3D::new(10., 10., 10.)).unwrap();
+        tree.insert(Point3D::new(10., 10., 90.)).unwrap();
+        tree.insert(Point3D::new(10., 10., 90.)).unwrap();
+        assert!(tree.insert(Point3D::new(10., 10., 101.)).is_err());
+
+        assert!(tree.contains(&Point3D::new(10., 10., 90.)));
+        assert!(!tree.contains(&Point3D::new(10., 90., 10.)));
+
+        // duplicates are removed one at a time
+        assert_eq!(tree.remove(&Point3D::new(10., 10., 90.)), Some(Point3D::new(10., 10., 90.)));
+        assert!(tree.contains(&Point3D::new(10., 10., 90.)));
+        tree.remove(&Point3D::new(10., 10., 90.));
+        assert!(!tree.contains(&Point3D::new(10., 10., 90.)));
+        assert_eq!(tree.remove(&Point3D::new(10., 10., 90.)), None);
+
+        // the emptied branches collapse
+        assert_eq!(tree.bounds().len(), 1);
+        assert_eq!(tree.remove_by_key(a), Some(Point3D::new(10., 10., 10.)));
+        assert!(tree.bounds().is_empty());
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Star {
+        name: String,
+        pos: [f32; 3],
+    }
+
+    impl Spatial3D for Star {
+        type Scalar = f32;
+        fn x(&self) -> f32 {self.pos[0]}
+        fn y(&self) -> f32 {self.pos[1]}
+        fn z(&self) -> f32 {self.pos[2]}
+    }
+
+    #[test]
+    fn test_owned_items() {
+        let star = |name: &str, x, y, z| Star { name: name.to_string(), pos: [x, y, z] };
+        let mut tree = PointerOctree::new(Bounds3D::new(0., 100., 0., 100., 0., 100.));
+        let sun = tree.insert(star("sun", 50., 50., 50.)).unwrap();
+        tree.insert(star("vega", 60., 50., 50.)).unwrap();
+        tree.insert(star("rigel", 10., 90., 10.)).unwrap();
+
+        assert_eq!(tree.closest(&star("sun", 50., 50., 50.)).map(|s| s.name.as_str()), Some("vega"));
+        let names: Vec<&str> = tree.k_nearest(&[55., 50., 50.], 2, None).into_iter().map(|(s, _)| s.name.as_str()).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"sun") && names.contains(&"vega"));
+        assert_eq!(tree.within(&[10., 90., 12.], 5.), vec![&star("rigel", 10., 90., 10.)]);
+        assert_eq!(tree.remove_by_key(sun).map(|s| s.name), Some("sun".to_string()));
+        assert!(!tree.contains(&star("sun", 50., 50., 50.)));
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut tree = PointerOctree::builder(Bounds3D::new(0., 100., 0., 100., 0., 100.))
+            .leaf_capacity(4)
+            .build();
+        for x in 0..5 {
+            for z in 0..5 {
+                tree.insert(Point3D::new(x as f32 * 20., 50., z as f32 * 20.)).unwrap();
+            }
+        }
+
+        let found = tree.query_bounds(&Bounds3D::new(30., 50., 0., 100., 30., 50.)).count();
+        assert_eq!(found, 1);
+        assert_eq!(tree.query_bounds(&Bounds3D::new(0., 100., 0., 100., 0., 100.)).count(), 25);
+        assert_eq!(tree.query_bounds(&Bounds3D::new(0., 100., 0., 40., 0., 100.)).count(), 0);
+
+        let mut found = tree.within(&Point3D::new(40., 50., 40.), 20.);
+        found.sort_by(|a, b| (a.x, a.z).partial_cmp(&(b.x, b.z)).unwrap());
+        assert_eq!(found, vec![
+            &Point3D::new(20., 50., 40.), &Point3D::new(40., 50., 20.), &Point3D::new(40., 50., 40.),
+            &Point3D::new(40., 50., 60.), &Point3D::new(60., 50., 40.),
+        ]);
+
+        assert_eq!(tree.closest(&Point3D::new(40., 50., 40.)).map(|p| p.distance_to(&[40., 50., 40.])), Some(20.));
+        let found = tree.k_nearest(&Point3D::new(41., 50., 40.), 2, None);
+        assert_eq!(found, vec![(&Point3D::new(40., 50., 40.), 1.), (&Point3D::new(60., 50., 40.), 19.)]);
+    }
+}
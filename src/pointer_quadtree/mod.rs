@@ -1,4 +1,8 @@
+#[allow(clippy::module_inception)]
 mod pointer_quadtree;
 
 pub use self::pointer_quadtree::PointerQuadtree as PointerQuadtree;
-pub use self::pointer_quadtree::BoundType as BoundType;
\ No newline at end of file
+pub use self::pointer_quadtree::BoundType as BoundType;
+pub use self::pointer_quadtree::QueryBounds as QueryBounds;
+pub use self::pointer_quadtree::PointerQuadtreeBuilder as PointerQuadtreeBuilder;
+pub use self::pointer_quadtree::ItemMut as ItemMut;
@@ -1,119 +1,549 @@
-
-use std::fmt;
+use std::collections::BinaryHeap;
+use std::iter::{self, FromIterator};
 use std::marker::PhantomData;
-use slotmap::{SlotMap, DefaultKey, Values, ValuesMut};
-use crate::core::{Spatial2D, Bounds, Quadrant};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::slice;
+use slotmap::DenseSlotMap;
+use slotmap::dense::{Values, ValuesMut};
+use crate::core::{Spatial2D, SpatialKey, SpatialIndex, Bounds, Scalar, Quadrant, QUADRANTS, Candidate, CandidateEntry, Result, SpatialError};
 
 const MAX_RECURCION: u32 = 8;
+/// Number of levels encoded by the Morton codes used for bulk loading
+const MORTON_LEVELS: u32 = 31;
 
 #[derive(Debug)]
 pub struct PointerQuadtree<T>
-    where T: Copy {
-    container: SlotMap<DefaultKey, T>,
+    where T: Spatial2D {
+    container: DenseSlotMap<SpatialKey, T>,
     root: QuadtreeNode<T>,
-    pub bounds: Bounds
+    config: QuadtreeConfig<T::Scalar>,
+    auto_grow: bool,
+    pub bounds: Bounds<T::Scalar>
+}
+
+/// Limits on how far nodes of the tree are subdivided
+#[derive(Clone, Copy, Debug)]
+pub struct QuadtreeConfig<S = f32> {
+    max_depth: u32,
+    leaf_capacity: usize,
+    min_size: S,
+}
+
+impl<S> QuadtreeConfig<S>
+    where S: Scalar {
+    /// Returns true if a node with these bounds at this depth
+    /// may be split into four children
+    fn can_split(&self, bounds: Bounds<S>, r_lvl: u32) -> bool {
+        r_lvl < self.max_depth &&
+        bounds.width().half() >= self.min_size &&
+        bounds.height().half() >= self.min_size
+    }
+}
+
+/// Bounds and depth of the node being visited, along with the
+/// limits of the tree it belongs to
+#[derive(Clone, Copy)]
+struct NodeContext<'a, S> {
+    bounds: Bounds<S>,
+    config: &'a QuadtreeConfig<S>,
+    r_lvl: u32,
+}
+
+impl<'a, S> NodeContext<'a, S>
+    where S: Scalar {
+    /// Returns the context of the child at `quadrant`
+    fn child(self, quadrant: Quadrant) -> Self {
+        NodeContext {
+            bounds: self.bounds.sub_bound(quadrant),
+            r_lvl: self.r_lvl + 1,
+            ..self
+        }
+    }
+}
+
+impl<S> Default for QuadtreeConfig<S>
+    where S: Scalar {
+    fn default() -> Self {
+        QuadtreeConfig {
+            max_depth: MAX_RECURCION,
+            leaf_capacity: 1,
+            min_size: S::ZERO,
+        }
+    }
 }
 
 impl<T> PointerQuadtree<T>
-    where T: Copy {
-    pub fn new(bounds: Bounds) -> Self {
+    where T: Spatial2D {
+    pub fn new(bounds: Bounds<T::Scalar>) -> Self {
+        PointerQuadtree::builder(bounds).build()
+    }
+
+    /// Returns a builder for a tree with custom node limits
+    pub fn builder(bounds: Bounds<T::Scalar>) -> PointerQuadtreeBuilder<T> {
+        PointerQuadtreeBuilder {
+            bounds,
+            config: QuadtreeConfig::default(),
+            auto_grow: false,
+            _phantom_data: PhantomData
+        }
+    }
+}
+
+/// Builds a [`PointerQuadtree`] with custom node limits
+///
+/// ```
+/// use spatial::core::{Bounds, Point2D};
+/// use spatial::pointer_quadtree::PointerQuadtree;
+///
+/// let quadtree = PointerQuadtree::<Point2D>::builder(Bounds::new(0., 1., 0., 1.))
+///     .max_depth(12)
+///     .leaf_capacity(8)
+///     .min_size(0.001)
+///     .auto_grow(true)
+///     .build();
+/// ```
+#[derive(Debug)]
+pub struct PointerQuadtreeBuilder<T>
+    where T: Spatial2D {
+    bounds: Bounds<T::Scalar>,
+    config: QuadtreeConfig<T::Scalar>,
+    auto_grow: bool,
+    _phantom_data: PhantomData<T>
+}
+
+impl<T> PointerQuadtreeBuilder<T>
+    where T: Spatial2D {
+    /// Sets the deepest level nodes are split to. Leaves at this
+    /// depth hold any number of items. Defaults to 8
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.config.max_depth = max_depth;
+        self
+    }
+
+    /// Sets how many items a leaf holds before it is split.
+    /// Defaults to 1
+    pub fn leaf_capacity(mut self, leaf_capacity: usize) -> Self {
+        self.config.leaf_capacity = leaf_capacity.max(1);
+        self
+    }
+
+    /// Sets the smallest width or height a node may be split
+    /// into. Defaults to 0
+    pub fn min_size(mut self, min_size: T::Scalar) -> Self {
+        self.config.min_size = min_size;
+        self
+    }
+
+    /// Sets whether items outside of the tree bounds are accepted by
+    /// growing the bounds until they hold them. Defaults to false
+    pub fn auto_grow(mut self, auto_grow: bool) -> Self {
+        self.auto_grow = auto_grow;
+        self
+    }
+
+    pub fn build(self) -> PointerQuadtree<T> {
         PointerQuadtree {
-            container: SlotMap::new(),
+            container: DenseSlotMap::with_key(),
             root: QuadtreeNode::Empty,
-            bounds
+            config: self.config,
+            auto_grow: self.auto_grow,
+            bounds: self.bounds
         }
     }
 }
 
 impl<T> PointerQuadtree<T>
-    where T: Spatial2D + Copy + PartialEq {
+    where T: Spatial2D + PartialEq {
 
-    pub fn try_insert(&mut self, data: T) {
+    /// Inserts the item and returns a persistent key that indexes it,
+    /// or an `OutOfBounds` error if it lies outside of the tree bounds
+    /// and the tree does not grow automatically
+    pub fn try_insert(&mut self, data: T) -> Result<SpatialKey> {
+        self.make_room(&data)?;
+        let key = self.container.insert(data);
+        self.root.insert(key, self.bounds, &self.container, &self.config, 0);
+        Ok(key)
+    }
 
-        if !self.bounds.is_point_within(&data) {
-            return;
+    pub fn insert(&mut self, data: T) -> Result<SpatialKey> {
+        self.try_insert(data)
+    }
+
+    /// Inserts every item and returns their keys in the same order, or an
+    /// `OutOfBounds` error without inserting any of them if one lies outside
+    /// of the tree bounds and the tree does not grow automatically.
+    ///
+    /// The items are sorted by Morton code. An empty tree is built from them
+    /// rather than descending from the root once per item, while items loaded
+    /// into a tree that already holds some are inserted in that order
+    pub fn bulk_load<I>(&mut self, items: I) -> Result<Vec<SpatialKey>>
+        where I: IntoIterator<Item = T> {
+        let items: Vec<T> = items.into_iter().collect();
+        for item in &items {
+            self.make_room(item)?;
+        }
+        let empty = self.container.is_empty();
+        let keys: Vec<SpatialKey> = items.into_iter()
+            .map(|item| self.container.insert(item))
+            .collect();
+        if empty {
+            self.rebuild_tree();
+            return Ok(keys);
         }
+        let mut entries: Vec<(u64, SpatialKey)> = keys.iter()
+            .map(|&key| (self.morton_code(&self.container[key]), key))
+            .collect();
+        entries.sort_by_key(|&(code, _)| code);
+        for (_, key) in entries {
+            self.root.insert(key, self.bounds, &self.container, &self.config, 0);
+        }
+        Ok(keys)
+    }
 
-        let key = self.container.insert(data);
-        self.root.insert(key, self.bounds, &self.container, 0);
+    pub fn get(&self, key: SpatialKey) -> Option<&T> {
+        self.container.get(key)
+    }
+
+    /// Returns mutable access to the item at `key`. If its position
+    /// changed, the item is moved within the tree once the returned guard
+    /// is dropped, the same way [`PointerQuadtree::move_all`] moves it.
+    /// Unless the tree grows automatically, an item moved out of the tree
+    /// bounds is removed; use [`PointerQuadtree::update`] to keep it instead
+    pub fn get_mut(&mut self, key: SpatialKey) -> Option<ItemMut<'_, T>> {
+        let item = self.container.get(key)?;
+        let old = [item.x(), item.y()];
+        Some(ItemMut { tree: self, key, old })
     }
 
-    pub fn insert(&mut self, data: T) {
-        self.try_insert(data);
+    pub fn contains_key(&self, key: SpatialKey) -> bool {
+        self.container.contains_key(key)
     }
 
-    pub fn contains(&self, p: T) -> bool {
-        if !self.bounds.is_point_within(&p) {
+    /// Removes the item at `key` from the tree and returns it
+    pub fn remove_by_key(&mut self, key: SpatialKey) -> Option<T> {
+        let item = self.container.remove(key)?;
+        self.detach(key, &item);
+        Some(item)
+    }
+
+    pub fn contains(&self, p: &T) -> bool {
+        if !self.bounds.contains_point(p) {
             false
         } else {
             self.root.contains(p, &self.container, self.bounds)
         }
     }
 
-    pub fn bounds(&self) -> Vec<Bounds> {
+    /// Replaces the item at `key` with `data` and returns the old item,
+    /// or none if there is no item at `key`.
+    ///
+    /// If `data` lies in the same node as the old item only the stored
+    /// value changes. Otherwise the item is removed from its node and
+    /// inserted again below the deepest branch that holds both the old
+    /// and new position
+    pub fn update(&mut self, key: SpatialKey, data: T) -> Result<Option<T>> {
+        if !self.contains_key(key) {
+            return Ok(None);
+        }
+        self.make_room(&data)?;
+        let old = match self.container.get_mut(key) {
+            Some(item) => std::mem::replace(item, data),
+            None => return Ok(None)
+        };
+        self.reposition(key, &old);
+        Ok(Some(old))
+    }
+
+    /// Calls `f` on every item and re-indexes the ones that moved.
+    ///
+    /// Unless the tree grows automatically, items that `f` moves out of
+    /// the tree bounds are removed and returned along with their keys
+    pub fn move_all<F>(&mut self, mut f: F) -> Vec<(SpatialKey, T)>
+        where F: FnMut(SpatialKey, &mut T) {
+        let keys: Vec<SpatialKey> = self.container.keys().collect();
+        let mut outside = vec![];
+        for key in keys {
+            let item = &mut self.container[key];
+            let old = [item.x(), item.y()];
+            f(key, item);
+            if let Some(item) = self.settle(key, old) {
+                outside.push((key, item));
+            }
+        }
+        outside
+    }
+
+    /// Moves `key` from the node holding `old` to the node holding the
+    /// current position of its item. Unless the tree grows automatically,
+    /// an item that left the tree bounds is removed and returned
+    fn settle(&mut self, key: SpatialKey, old: [T::Scalar; 2]) -> Option<T> {
+        let item = &self.container[key];
+        let new = [item.x(), item.y()];
+        if new == old {
+            return None;
+        }
+        if self.bounds.contains_point(&new) {
+            self.reposition(key, &old);
+            return None;
+        }
+        // growing the tree looks items up by their position,
+        // so the moved item leaves the tree first
+        self.detach(key, &old);
+        if self.make_room(&new).is_ok() {
+            self.root.insert(key, self.bounds, &self.container, &self.config, 0);
+            None
+        } else {
+            self.container.remove(key)
+        }
+    }
+
+    /// Removes `key` from the tree, looking for it in the node holding `old`
+    fn detach(&mut self, key: SpatialKey, old: &dyn Spatial2D<Scalar = T::Scalar>) {
+        let removed = self.root.remove_key(key, old, self.bounds, &self.config);
+        debug_assert!(removed, "items are stored in the node holding their position");
+    }
+
+    /// Moves `key` from the node holding `old` to the node holding
+    /// the current position of its item
+    fn reposition(&mut self, key: SpatialKey, old: &dyn Spatial2D<Scalar = T::Scalar>) {
+        let ctx = NodeContext { bounds: self.bounds, config: &self.config, r_lvl: 0 };
+        let moved = self.root.relocate(key, old, &self.container[key], &self.container, ctx);
+        debug_assert!(moved, "items are stored in the node holding their position");
+    }
+
+    pub fn bounds(&self) -> Vec<Bounds<T::Scalar>> {
         let mut vec = vec![];
         self.root.bounds(&mut vec, self.bounds);
         vec
     }
 
-    pub fn bounds_with_type(&self) -> Vec<(Bounds, BoundType)> {
+    pub fn bounds_with_type(&self) -> Vec<(Bounds<T::Scalar>, BoundType)> {
         let mut vec = vec![];
         self.root.bounds_with_type(&mut vec, self.bounds);
         vec
     }
 
+    /// Rebuilds the tree from the current position of every item
     pub fn rebuild_tree(&mut self) {
-        self.root = QuadtreeNode::Empty;
-        for key in self.container.keys() {
-            self.root.insert(key, self.bounds, &self.container, 0);
+        let mut entries: Vec<(u64, SpatialKey)> = self.container.iter()
+            .map(|(key, item)| (self.morton_code(item), key))
+            .collect();
+        entries.sort_by_key(|&(code, _)| code);
+        self.root = QuadtreeNode::build(&entries, self.bounds, &self.container, &self.config, 0);
+    }
+
+    /// Returns the Morton code of the cell holding `item` at the depth limit.
+    /// Cells are found by halving the bounds the way inserts do, so items
+    /// on a center line are sorted into the node an insert would pick
+    fn morton_code(&self, item: &T) -> u64 {
+        let levels = self.config.max_depth.min(MORTON_LEVELS);
+        let mut bounds = self.bounds;
+        let mut code = 0;
+        for _ in 0..levels {
+            let (quadrant, sub_bound) = bounds.split_towards(item);
+            code = code << 2 | quadrant.index() as u64;
+            bounds = sub_bound;
         }
+        code << (2 * (MORTON_LEVELS - levels))
     }
 
-    pub fn remove(&mut self, p: T) {
-        if let Some(key) = self.root.remove(p, &self.container, self.bounds) {
-            self.container.remove(key);
+    /// Grows the tree bounds until they hold `p` if the tree grows
+    /// automatically, or returns an `OutOfBounds` error otherwise
+    fn make_room(&mut self, p: &dyn Spatial2D<Scalar = T::Scalar>) -> Result<()> {
+        while !self.bounds.contains_point(p) {
+            let grown = if self.auto_grow { self.bounds.double_towards(p) } else { None };
+            match grown {
+                Some((bounds, quadrant)) => self.grow(bounds, quadrant),
+                None => return Err(SpatialError::out_of_bounds(p.pos(), self.bounds))
+            }
         }
+        Ok(())
     }
 
-    pub fn within(&self, p: &dyn Spatial2D, radius: f32) -> Vec<T> {
+    /// Replaces the tree bounds with `bounds`, twice their size, keeping the
+    /// old root as the child at `quadrant`. The depth limit grows along with
+    /// the tree, so nodes keep their size limit
+    fn grow(&mut self, bounds: Bounds<T::Scalar>, quadrant: Quadrant) {
+        let old_bounds = self.bounds;
+        self.config.max_depth = self.config.max_depth.saturating_add(1);
+        // leaves hold their items regardless of their bounds
+        if !matches!(self.root, QuadtreeNode::Branch(_)) {
+            self.bounds = bounds;
+            return;
+        }
 
-        let vec = vec![];
-        let mut enclosing_bound = Bounds::new(
-            p.x() - radius, p.x() + radius, p.y() - radius, p.y() + radius
-        );
-        if !enclosing_bound.overlaps(self.bounds) {return vec;}
-        enclosing_bound.truncate(self.bounds);
+        // items on the old edges that now face the center belong to the
+        // quadrant next to the old root and are inserted again
+        let edges = [
+            Bounds::new(old_bounds.x_min, old_bounds.x_min, old_bounds.y_min, old_bounds.y_max),
+            Bounds::new(old_bounds.x_min, old_bounds.x_max, old_bounds.y_min, old_bounds.y_min),
+        ];
+        let mut moved = vec![];
+        for edge in edges.iter() {
+            let mut query = self.query_bounds(edge);
+            moved.extend(
+                iter::from_fn(|| query.next_entry())
+                    .filter(|(_, item)| bounds.find_quadrant(*item) != quadrant)
+                    .map(|(key, _)| key)
+            );
+        }
+        moved.sort();
+        moved.dedup();
+        for &key in &moved {
+            self.root.remove_key(key, &self.container[key], old_bounds, &self.config);
+        }
+        self.bounds = bounds;
 
+        // the removals may have collapsed the old root into a leaf
+        if matches!(self.root, QuadtreeNode::Branch(_)) {
+            let old_root = mem::replace(&mut self.root, QuadtreeNode::new_branch());
+            if let QuadtreeNode::Branch(branch) = &mut self.root {
+                *branch.child_mut(quadrant) = old_root;
+            }
+        }
+        for key in moved {
+            self.root.insert(key, self.bounds, &self.container, &self.config, 0);
+        }
+    }
+
+    /// Merges every branch whose children fit into a single leaf.
+    /// Removals already collapse the branches along their path
+    pub fn compact(&mut self) {
+        self.root.compact(&self.config);
+    }
+
+    /// Removes an item equal to `p` from the tree and returns it
+    pub fn remove(&mut self, p: &T) -> Option<T> {
+        let key = self.root.remove(p, &self.container, self.bounds, &self.config)?;
+        self.container.remove(key)
+    }
+
+    /// Returns every item whose distance to `p` is at most `radius`
+    pub fn within(&self, p: &dyn Spatial2D<Scalar = T::Scalar>, radius: T::Scalar) -> Vec<&T> {
+        let mut vec = vec![];
+        if !T::Scalar::within_radius(self.bounds.squared_distance_to_point(p), radius) {
+            return vec;
+        }
+        self.root.within(p, radius, &self.container, self.bounds, &mut vec);
         vec
+    }
 
+    /// Returns an iterator over every item inside `query`, visiting
+    /// only the nodes whose bounds intersect it
+    pub fn query_bounds(&self, query: &Bounds<T::Scalar>) -> QueryBounds<'_, T> {
+        let mut stack = vec![];
+        // points inside the query can only be stored below this node
+        let start = self.bounds.intersection(*query)
+            .and_then(|window| self.root.smallest_enclosing(window, self.bounds));
+        if let Some(start) = start {
+            stack.push(start);
+        }
+        QueryBounds {
+            container: &self.container,
+            query: *query,
+            stack,
+            pending: [].iter()
+        }
     }
 
-    pub fn closest(&self, p: T) -> Option<T> {
-        None
+    /// Returns the item closest to `p`, skipping any stored item equal to `p`
+    pub fn closest(&self, p: &T) -> Option<&T> {
+        self.nearest(p)
+            .map(|(key, _)| &self.container[key])
+            .find(|item| *item != p)
     }
 
-    pub fn neighbors(&self, p: T) -> Vec<T> {
+    /// Returns up to `k` items closest to `p` along with their distances,
+    /// sorted from nearest to farthest. If `max_dist` is given, items
+    /// farther away than it are not returned
+    pub fn k_nearest(&self, p: &dyn Spatial2D<Scalar = T::Scalar>, k: usize, max_dist: Option<T::Scalar>) -> Vec<(&T, T::Scalar)> {
+        self.nearest(p)
+            .take_while(|&(_, squared)| match max_dist {
+                Some(max) => T::Scalar::within_radius(squared, max),
+                None => true
+            })
+            .take(k)
+            .map(|(key, squared)| (&self.container[key], T::Scalar::from_squared(squared)))
+            .collect()
+    }
+
+    /// Returns an iterator over stored keys in order of increasing distance to `p`
+    fn nearest(&self, p: &dyn Spatial2D<Scalar = T::Scalar>) -> Nearest<'_, T> {
+        let point = [p.x(), p.y()];
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate::node(
+            self.bounds.squared_distance_to_point(&point),
+            (&self.root, self.bounds)
+        ));
+        Nearest {
+            container: &self.container,
+            point,
+            queue
+        }
+    }
+
+    pub fn neighbors(&self, _p: &T) -> Vec<&T> {
         vec![]
     }
 
-    pub fn values(&self) -> Values<DefaultKey, T> {
+    pub fn values(&self) -> Values<'_, SpatialKey, T> {
         self.container.values()
     }
 
-    pub fn values_mut(&mut self) -> ValuesMut<DefaultKey, T> {
+    /// Returns an iterator over mutable references to every item. Changing
+    /// their position does not move them within the tree until
+    /// [`PointerQuadtree::rebuild_tree`] is called
+    pub fn values_mut(&mut self) -> ValuesMut<'_, SpatialKey, T> {
         self.container.values_mut()
     }
 }
 
+/// Mutable access to an item, created by [`PointerQuadtree::get_mut`].
+/// The item is moved within the tree when the guard is dropped
+pub struct ItemMut<'a, T>
+    where T: Spatial2D + PartialEq {
+    tree: &'a mut PointerQuadtree<T>,
+    key: SpatialKey,
+    old: [T::Scalar; 2],
+}
+
+impl<'a, T> Deref for ItemMut<'a, T>
+    where T: Spatial2D + PartialEq {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.tree.container[self.key]
+    }
+}
+
+impl<'a, T> DerefMut for ItemMut<'a, T>
+    where T: Spatial2D + PartialEq {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.tree.container[self.key]
+    }
+}
+
+impl<'a, T> Drop for ItemMut<'a, T>
+    where T: Spatial2D + PartialEq {
+    fn drop(&mut self) {
+        self.tree.settle(self.key, self.old);
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum QuadtreeNode<T> {
-    Saturated(Vec<DefaultKey>),
+    /// node at the depth or size limit holding more items than
+    /// the leaf capacity
+    Saturated(Vec<SpatialKey>),
     Branch(Branch<T>),
-    Leaf(DefaultKey),
+    /// node holding up to the leaf capacity of items
+    Leaf(Vec<SpatialKey>),
     Empty,
 }
 
 #[derive(Debug, PartialEq)]
+#[allow(non_snake_case)]
 pub struct Branch<T> {
     TL: Box<QuadtreeNode<T>>,
     TR: Box<QuadtreeNode<T>>,
@@ -122,14 +552,193 @@ pub struct Branch<T> {
     _phantom_data: PhantomData<T>
 }
 
+impl<T> Branch<T> {
+    /// Returns each child node paired with the quadrant it covers
+    fn children(&self) -> [(&QuadtreeNode<T>, Quadrant); 4] {
+        [
+            (&self.TL, Quadrant::TL),
+            (&self.TR, Quadrant::TR),
+            (&self.BL, Quadrant::BL),
+            (&self.BR, Quadrant::BR),
+        ]
+    }
+
+    fn child(&self, quadrant: Quadrant) -> &QuadtreeNode<T> {
+        match quadrant {
+            Quadrant::TL => &self.TL,
+            Quadrant::TR => &self.TR,
+            Quadrant::BL => &self.BL,
+            Quadrant::BR => &self.BR,
+        }
+    }
+
+    fn child_mut(&mut self, quadrant: Quadrant) -> &mut QuadtreeNode<T> {
+        match quadrant {
+            Quadrant::TL => &mut self.TL,
+            Quadrant::TR => &mut self.TR,
+            Quadrant::BL => &mut self.BL,
+            Quadrant::BR => &mut self.BR,
+        }
+    }
+}
+
+/// Iterator over the items inside a query region, created by
+/// [`PointerQuadtree::query_bounds`]
+pub struct QueryBounds<'a, T>
+    where T: Spatial2D {
+    container: &'a DenseSlotMap<SpatialKey, T>,
+    query: Bounds<T::Scalar>,
+    stack: Vec<(&'a QuadtreeNode<T>, Bounds<T::Scalar>)>,
+    pending: slice::Iter<'a, SpatialKey>,
+}
+
+impl<'a, T> QueryBounds<'a, T>
+    where T: Spatial2D {
+    fn next_entry(&mut self) -> Option<(SpatialKey, &'a T)> {
+        loop {
+            for key in &mut self.pending {
+                let item = &self.container[*key];
+                if self.query.contains_point(item) {
+                    return Some((*key, item));
+                }
+            }
+            let (node, curr_bound) = self.stack.pop()?;
+            match node {
+                QuadtreeNode::Leaf(keys) | QuadtreeNode::Saturated(keys) => self.pending = keys.iter(),
+                QuadtreeNode::Branch(branch) => {
+                    for &(child, quadrant) in branch.children().iter() {
+                        let child_bound = curr_bound.sub_bound(quadrant);
+                        if child_bound.intersects(self.query) {
+                            self.stack.push((child, child_bound));
+                        }
+                    }
+                }
+                QuadtreeNode::Empty => ()
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for QueryBounds<'a, T>
+    where T: Spatial2D {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().map(|(_, item)| item)
+    }
+}
+
+type NearestCandidate<'a, T> = Candidate<
+    (&'a QuadtreeNode<T>, Bounds<<T as Spatial2D>::Scalar>),
+    SpatialKey,
+    <<T as Spatial2D>::Scalar as Scalar>::Squared
+>;
+
+/// Best-first traversal yielding stored keys and their squared distances to
+/// a point, nearest first. Nodes are expanded lazily in order of their
+/// minimum possible distance, so consumers can stop as soon as they
+/// have seen enough items
+struct Nearest<'a, T>
+    where T: Spatial2D {
+    container: &'a DenseSlotMap<SpatialKey, T>,
+    point: [T::Scalar; 2],
+    queue: BinaryHeap<NearestCandidate<'a, T>>,
+}
+
+impl<'a, T> Nearest<'a, T>
+    where T: Spatial2D {
+    fn push_item(&mut self, key: SpatialKey) {
+        let squared = self.container[key].squared_distance_to(&self.point);
+        self.queue.push(Candidate::item(squared, key));
+    }
+}
+
+impl<'a, T> Iterator for Nearest<'a, T>
+    where T: Spatial2D {
+    type Item = (SpatialKey, <T::Scalar as Scalar>::Squared);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Candidate { distance, entry }) = self.queue.pop() {
+            match entry {
+                CandidateEntry::Item(key) => return Some((key, distance)),
+                CandidateEntry::Node((node, curr_bound)) => match node {
+                    QuadtreeNode::Leaf(keys) | QuadtreeNode::Saturated(keys) => {
+                        for &key in keys {
+                            self.push_item(key);
+                        }
+                    }
+                    QuadtreeNode::Branch(branch) => {
+                        for &(child, quadrant) in branch.children().iter() {
+                            let child_bound = curr_bound.sub_bound(quadrant);
+                            self.queue.push(Candidate::node(
+                                child_bound.squared_distance_to_point(&self.point),
+                                (child, child_bound)
+                            ));
+                        }
+                    }
+                    QuadtreeNode::Empty => ()
+                }
+            }
+        }
+        None
+    }
+}
+
 impl<T> QuadtreeNode<T>
-    where T: Spatial2D + Copy + PartialEq
+    where T: Spatial2D + PartialEq
 {
+    /// Builds the node holding `entries`, which are sorted by the Morton
+    /// codes of their items, splitting it as far as inserting them one
+    /// at a time would
+    fn build(
+        entries: &[(u64, SpatialKey)],
+        bounds: Bounds<T::Scalar>,
+        container: &DenseSlotMap<SpatialKey, T>,
+        config: &QuadtreeConfig<T::Scalar>,
+        r_lvl: u32
+    ) -> Self {
+        let keys = || entries.iter().map(|&(_, key)| key).collect();
+        if entries.is_empty() {
+            return QuadtreeNode::Empty;
+        }
+        if entries.len() <= config.leaf_capacity.max(1) {
+            return QuadtreeNode::Leaf(keys());
+        }
+        if !config.can_split(bounds, r_lvl) {
+            return QuadtreeNode::Saturated(keys());
+        }
+
+        let mut node = QuadtreeNode::new_branch();
+        // the codes no longer tell the items apart below this level
+        if r_lvl >= MORTON_LEVELS {
+            for &(_, key) in entries {
+                node.insert(key, bounds, container, config, r_lvl);
+            }
+            return node;
+        }
+        if let QuadtreeNode::Branch(branch) = &mut node {
+            let shift = 2 * (MORTON_LEVELS - 1 - r_lvl);
+            let mut rest = entries;
+            for &quadrant in QUADRANTS.iter() {
+                let end = rest.partition_point(|&(code, _)| {
+                    (code >> shift & 0b11) as usize <= quadrant.index()
+                });
+                let (run, tail) = rest.split_at(end);
+                *branch.child_mut(quadrant) = QuadtreeNode::build(
+                    run, bounds.sub_bound(quadrant), container, config, r_lvl + 1
+                );
+                rest = tail;
+            }
+        }
+        node
+    }
+
     pub fn insert(
         &mut self,
-        key: DefaultKey,
-        bounds: Bounds,
-        container: &SlotMap<DefaultKey, T>,
+        key: SpatialKey,
+        bounds: Bounds<T::Scalar>,
+        container: &DenseSlotMap<SpatialKey, T>,
+        config: &QuadtreeConfig<T::Scalar>,
         r_lvl: u32
         )  {
 
@@ -139,45 +748,26 @@ impl<T> QuadtreeNode<T>
             }
             QuadtreeNode::Branch(_) => {
                 let quadrant = bounds.find_quadrant(&container[key]);
-                self.insert_in_branch(key, quadrant, bounds.sub_bound(quadrant), container, r_lvl);
+                self.insert_in_branch(key, quadrant, bounds.sub_bound(quadrant), container, config, r_lvl);
             }
-            QuadtreeNode::Leaf(other_key) => {
-
-                let other_key = *other_key;
-                if r_lvl == MAX_RECURCION {
-                    *self = QuadtreeNode::Saturated(vec![key, other_key]);
+            QuadtreeNode::Leaf(vec) => {
+                if vec.len() < config.leaf_capacity {
+                    vec.push(key);
+                } else if !config.can_split(bounds, r_lvl) {
+                    let mut keys = mem::take(vec);
+                    keys.push(key);
+                    *self = QuadtreeNode::Saturated(keys);
                 } else {
+                    let keys = mem::take(vec);
                     *self = QuadtreeNode::new_branch();
-
-                    let (q1, q2) = {
-                        let p1 = &container[key] as &Spatial2D;
-                        let p2 = &container[other_key] as &Spatial2D;
-
-                        let q1 = bounds.find_quadrant(p1);
-                        let q2 = bounds.find_quadrant(p2);
-
-                        //println!("P1: {:?} in {:?} | P2: {:?} in {:?}", p1, q1, p2, q2);
-
-                        (q1, q2)
-                    };
-
-
-
-
-                    if q1 == q2 {
-                        let new_bound = bounds.sub_bound(q1);
-                        self.insert_in_branch(key, q1,new_bound, container, r_lvl);
-                        self.insert_in_branch(other_key, q1,new_bound, container, r_lvl);
-                    } else {
-                        let new_bound = bounds.sub_bound(q1);
-                        self.insert_in_branch(key, q1,new_bound, container, r_lvl);
-                        let new_bound = bounds.sub_bound(q2);
-                        self.insert_in_branch(other_key, q2,new_bound, container, r_lvl);
+                    for other_key in keys.into_iter().chain(Some(key)) {
+                        let quadrant = bounds.find_quadrant(&container[other_key]);
+                        self.insert_in_branch(other_key, quadrant, bounds.sub_bound(quadrant), container, config, r_lvl);
                     }
                 }
             }
             QuadtreeNode::Empty => {
-                *self = QuadtreeNode::Leaf(key);
+                *self = QuadtreeNode::Leaf(vec![key]);
             }
         }
     }
@@ -196,23 +786,19 @@ impl<T> QuadtreeNode<T>
 
     pub fn insert_in_branch(
         &mut self,
-        key: DefaultKey,
+        key: SpatialKey,
         quadrant: Quadrant,
-        bounds: Bounds,
-        container: &SlotMap<DefaultKey, T>,
+        bounds: Bounds<T::Scalar>,
+        container: &DenseSlotMap<SpatialKey, T>,
+        config: &QuadtreeConfig<T::Scalar>,
         r_lvl: u32
     ) {
         if let QuadtreeNode::Branch(branch) = self {
-            match quadrant {
-                Quadrant::TL => branch.TL.insert(key, bounds, container, r_lvl+1),
-                Quadrant::TR => branch.TR.insert(key, bounds, container, r_lvl+1),
-                Quadrant::BL => branch.BL.insert(key, bounds, container, r_lvl+1),
-                Quadrant::BR => branch.BR.insert(key, bounds, container, r_lvl+1)
-            }
+            branch.child_mut(quadrant).insert(key, bounds, container, config, r_lvl+1);
         }
     }
 
-    fn bounds(&self, vec: &mut Vec<Bounds>, curr_bound: Bounds) {
+    fn bounds(&self, vec: &mut Vec<Bounds<T::Scalar>>, curr_bound: Bounds<T::Scalar>) {
         match self {
             QuadtreeNode::Saturated(_) => {
                 vec.push(curr_bound);
@@ -234,8 +820,8 @@ impl<T> QuadtreeNode<T>
 
     fn bounds_with_type(
         &self,
-        vec: &mut Vec<(Bounds, BoundType)>,
-        curr_bound: Bounds
+        vec: &mut Vec<(Bounds<T::Scalar>, BoundType)>,
+        curr_bound: Bounds<T::Scalar>
     ) {
         match self {
             QuadtreeNode::Saturated(_) => {
@@ -258,15 +844,15 @@ impl<T> QuadtreeNode<T>
 
     fn contains(
         &self,
-        p: T, container: &SlotMap<DefaultKey, T>,
-        curr_bound: Bounds
+        p: &T, container: &DenseSlotMap<SpatialKey, T>,
+        curr_bound: Bounds<T::Scalar>
     ) -> bool {
         match self {
-            QuadtreeNode::Saturated(vec) => {
-                vec.iter().any(|key| container[*key] == p)
+            QuadtreeNode::Leaf(vec) | QuadtreeNode::Saturated(vec) => {
+                vec.iter().any(|key| container[*key] == *p)
             }
             QuadtreeNode::Branch(branch) => {
-                let quadrant = curr_bound.find_quadrant(&p);
+                let quadrant = curr_bound.find_quadrant(p);
                 match quadrant {
                     Quadrant::TL => branch.TL.contains(p, container, curr_bound.sub_bound(quadrant)),
                     Quadrant::TR => branch.TR.contains(p, container, curr_bound.sub_bound(quadrant)),
@@ -274,13 +860,6 @@ impl<T> QuadtreeNode<T>
                     Quadrant::BR => branch.BR.contains(p, container, curr_bound.sub_bound(quadrant)),
                 }
             }
-            QuadtreeNode::Leaf(key) => {
-                if container[*key] == p {
-                    true
-                } else {
-                    false
-                }
-            }
             QuadtreeNode::Empty => {
                 false
             }
@@ -289,47 +868,253 @@ impl<T> QuadtreeNode<T>
 
     fn remove(
         &mut self,
-        p: T,
-        container: &SlotMap<DefaultKey, T>,
-        curr_bound: Bounds
-    ) -> Option<DefaultKey> {
+        p: &T,
+        container: &DenseSlotMap<SpatialKey, T>,
+        curr_bound: Bounds<T::Scalar>,
+        config: &QuadtreeConfig<T::Scalar>
+    ) -> Option<SpatialKey> {
         match self {
-            QuadtreeNode::Saturated(vec) => {
-                for (idx, key) in vec.clone().iter().enumerate() {
-                    if container[*key] == p {
-                        vec.remove(idx);
-                        if vec.len() == 1 {
-                            *self = QuadtreeNode::Leaf(*vec.get(0).unwrap())
-                        }
-                        return Some(*key)
+            QuadtreeNode::Leaf(vec) | QuadtreeNode::Saturated(vec) => {
+                let idx = vec.iter().position(|key| container[*key] == *p)?;
+                let key = vec.remove(idx);
+                self.shrink(config);
+                Some(key)
+            }
+            QuadtreeNode::Branch(branch) => {
+                let quadrant = curr_bound.find_quadrant(p);
+                let key = branch.child_mut(quadrant)
+                    .remove(p, container, curr_bound.sub_bound(quadrant), config)?;
+                self.collapse(config);
+                Some(key)
+            }
+            QuadtreeNode::Empty => None
+        }
+    }
+
+    fn within<'a>(
+        &self,
+        p: &dyn Spatial2D<Scalar = T::Scalar>,
+        radius: T::Scalar,
+        container: &'a DenseSlotMap<SpatialKey, T>,
+        curr_bound: Bounds<T::Scalar>,
+        vec: &mut Vec<&'a T>
+    ) {
+        // no part of this node can hold a point inside the circle
+        if !T::Scalar::within_radius(curr_bound.squared_distance_to_point(p), radius) {
+            return;
+        }
+        match self {
+            QuadtreeNode::Leaf(keys) | QuadtreeNode::Saturated(keys) => {
+                for key in keys {
+                    if T::Scalar::within_radius(container[*key].squared_distance_to(p), radius) {
+                        vec.push(&container[*key]);
                     }
                 }
-                None
             }
             QuadtreeNode::Branch(branch) => {
-                let quadrant = curr_bound.find_quadrant(&p);
-                match quadrant {
-                    Quadrant::TL => branch.TL.remove(p, container, curr_bound.sub_bound(quadrant)),
-                    Quadrant::TR => branch.TR.remove(p, container, curr_bound.sub_bound(quadrant)),
-                    Quadrant::BL => branch.BL.remove(p, container, curr_bound.sub_bound(quadrant)),
-                    Quadrant::BR => branch.BR.remove(p, container, curr_bound.sub_bound(quadrant)),
+                branch.TL.within(p, radius, container, curr_bound.sub_bound(Quadrant::TL), vec);
+                branch.TR.within(p, radius, container, curr_bound.sub_bound(Quadrant::TR), vec);
+                branch.BL.within(p, radius, container, curr_bound.sub_bound(Quadrant::BL), vec);
+                branch.BR.within(p, radius, container, curr_bound.sub_bound(Quadrant::BR), vec);
+            }
+            QuadtreeNode::Empty => ()
+        }
+    }
+
+    /// Moves `key` from the node holding `old` to the node holding `new`,
+    /// leaving the tree untouched above their deepest common branch.
+    /// Returns false without changing the tree if `key` is not in the
+    /// node holding `old`
+    fn relocate(
+        &mut self,
+        key: SpatialKey,
+        old: &dyn Spatial2D<Scalar = T::Scalar>,
+        new: &dyn Spatial2D<Scalar = T::Scalar>,
+        container: &DenseSlotMap<SpatialKey, T>,
+        ctx: NodeContext<'_, T::Scalar>
+    ) -> bool {
+        match self {
+            QuadtreeNode::Branch(branch) => {
+                let old_quadrant = ctx.bounds.find_quadrant(old);
+                let new_quadrant = ctx.bounds.find_quadrant(new);
+                if old_quadrant == new_quadrant {
+                    return branch.child_mut(old_quadrant)
+                        .relocate(key, old, new, container, ctx.child(old_quadrant));
                 }
+                let removed = branch.child_mut(old_quadrant)
+                    .remove_key(key, old, ctx.bounds.sub_bound(old_quadrant), ctx.config);
+                if removed {
+                    let bounds = ctx.bounds.sub_bound(new_quadrant);
+                    self.insert_in_branch(key, new_quadrant, bounds, container, ctx.config, ctx.r_lvl);
+                }
+                removed
+            }
+            // any other node already covers the new position
+            QuadtreeNode::Leaf(vec) | QuadtreeNode::Saturated(vec) => vec.contains(&key),
+            QuadtreeNode::Empty => false
+        }
+    }
+
+    /// Turns a branch back into a single leaf once its children hold
+    /// no more items than a leaf can
+    fn collapse(&mut self, config: &QuadtreeConfig<T::Scalar>) {
+        if let QuadtreeNode::Branch(branch) = self {
+            let mut keys = vec![];
+            for &(child, _) in branch.children().iter() {
+                match child {
+                    QuadtreeNode::Empty => (),
+                    QuadtreeNode::Leaf(vec) => keys.extend_from_slice(vec),
+                    // deeper structure is still needed
+                    _ => return
+                }
+            }
+            if keys.is_empty() {
+                *self = QuadtreeNode::Empty;
+            } else if keys.len() <= config.leaf_capacity {
+                *self = QuadtreeNode::Leaf(keys);
+            }
+        }
+    }
+
+    /// Collapses branches from the bottom up
+    fn compact(&mut self, config: &QuadtreeConfig<T::Scalar>) {
+        if let QuadtreeNode::Branch(branch) = self {
+            for quadrant in QUADRANTS.iter() {
+                branch.child_mut(*quadrant).compact(config);
+            }
+            self.collapse(config);
+        }
+    }
+
+    /// Turns a leaf or saturated node that has lost items into the
+    /// simplest node able to hold the rest
+    fn shrink(&mut self, config: &QuadtreeConfig<T::Scalar>) {
+        match self {
+            QuadtreeNode::Leaf(vec) | QuadtreeNode::Saturated(vec) if vec.is_empty() => {
+                *self = QuadtreeNode::Empty;
             }
-            QuadtreeNode::Leaf(key) => {
-                let key = key.clone();
-                if container[key] == p {
-                    *self = QuadtreeNode::Empty;
-                    Some(key)
+            QuadtreeNode::Saturated(vec) if vec.len() <= config.leaf_capacity => {
+                *self = QuadtreeNode::Leaf(mem::take(vec));
+            }
+            _ => ()
+        }
+    }
+
+    /// Removes `key` from the node that holds it, descending
+    /// towards the position of `item`
+    fn remove_key(
+        &mut self,
+        key: SpatialKey,
+        item: &dyn Spatial2D<Scalar = T::Scalar>,
+        curr_bound: Bounds<T::Scalar>,
+        config: &QuadtreeConfig<T::Scalar>
+    ) -> bool {
+        match self {
+            QuadtreeNode::Leaf(vec) | QuadtreeNode::Saturated(vec) => {
+                if let Some(idx) = vec.iter().position(|k| *k == key) {
+                    vec.remove(idx);
+                    self.shrink(config);
+                    true
                 } else {
-                    None
+                    false
                 }
             }
-            QuadtreeNode::Empty => None
+            QuadtreeNode::Branch(branch) => {
+                let quadrant = curr_bound.find_quadrant(item);
+                let removed = branch.child_mut(quadrant)
+                    .remove_key(key, item, curr_bound.sub_bound(quadrant), config);
+                if removed {
+                    self.collapse(config);
+                }
+                removed
+            }
+            QuadtreeNode::Empty => false
         }
     }
 
-    fn smallest_enclosing(&self, test_bound: Bounds, curr_bound: Bounds) -> Option<&QuadtreeNode<T>> {
-        None
+    /// Returns the deepest node whose bounds fully contain `test_bound`,
+    /// along with the bounds of that node
+    fn smallest_enclosing(&self, test_bound: Bounds<T::Scalar>, curr_bound: Bounds<T::Scalar>) -> Option<(&QuadtreeNode<T>, Bounds<T::Scalar>)> {
+        if !curr_bound.contains_bounds(test_bound) {
+            return None;
+        }
+        if let QuadtreeNode::Branch(branch) = self {
+            if let Some(quadrant) = curr_bound.enclosing_quadrant(test_bound) {
+                return branch.child(quadrant).smallest_enclosing(test_bound, curr_bound.sub_bound(quadrant));
+            }
+        }
+        Some((self, curr_bound))
+    }
+}
+
+impl<T> SpatialIndex for PointerQuadtree<T>
+    where T: Spatial2D + PartialEq {
+    type Item = T;
+
+    fn insert(&mut self, item: T) -> Result<SpatialKey> {
+        self.try_insert(item)
+    }
+
+    fn remove_by_key(&mut self, key: SpatialKey) -> Option<T> {
+        PointerQuadtree::remove_by_key(self, key)
+    }
+
+    fn get(&self, key: SpatialKey) -> Option<&T> {
+        self.container.get(key)
+    }
+
+    fn len(&self) -> usize {
+        self.container.len()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (SpatialKey, &T)> + '_> {
+        Box::new(self.container.iter())
+    }
+
+    fn query_bounds_keyed(&self, query: &Bounds<T::Scalar>) -> Box<dyn Iterator<Item = (SpatialKey, &T)> + '_> {
+        let mut query = PointerQuadtree::query_bounds(self, query);
+        Box::new(iter::from_fn(move || query.next_entry()))
+    }
+
+    fn within_keyed(&self, p: &dyn Spatial2D<Scalar = T::Scalar>, radius: T::Scalar) -> Vec<(SpatialKey, &T)> {
+        self.nearest(p)
+            .take_while(|&(_, squared)| T::Scalar::within_radius(squared, radius))
+            .map(|(key, _)| (key, &self.container[key]))
+            .collect()
+    }
+
+    fn k_nearest_keyed(&self, p: &dyn Spatial2D<Scalar = T::Scalar>, k: usize) -> Vec<(SpatialKey, &T, T::Scalar)> {
+        self.nearest(p)
+            .take(k)
+            .map(|(key, squared)| (key, &self.container[key], T::Scalar::from_squared(squared)))
+            .collect()
+    }
+}
+
+impl<T> FromIterator<T> for PointerQuadtree<T>
+    where T: Spatial2D + PartialEq {
+    /// Creates a tree around the items that grows automatically
+    fn from_iter<I>(items: I) -> Self
+        where I: IntoIterator<Item = T> {
+        let items: Vec<T> = items.into_iter().collect();
+        let mut tree = PointerQuadtree::builder(Bounds::around_points(&items))
+            .auto_grow(true)
+            .build();
+        tree.extend(items);
+        tree
+    }
+}
+
+impl<T> Extend<T> for PointerQuadtree<T>
+    where T: Spatial2D + PartialEq {
+    /// Bulk loads the items, skipping the ones that can not be inserted
+    fn extend<I>(&mut self, items: I)
+        where I: IntoIterator<Item = T> {
+        let items: Vec<T> = items.into_iter()
+            .filter(|item| self.make_room(item).is_ok())
+            .collect();
+        // every remaining item lies within the bounds
+        let _ = self.bulk_load(items);
     }
 }
 
@@ -344,3 +1129,336 @@ pub enum BoundType {
 
 
 
+
+
+#[cfg(test)]
+mod test {
+    use super::{PointerQuadtree, QuadtreeNode, BoundType};
+    use crate::core::{Bounds, Point2D, Spatial2D, SpatialError};
+
+    /// Item that owns heap data and can not be copied
+    #[derive(Debug, PartialEq)]
+    struct Named {
+        name: String,
+        pos: [f32; 2],
+    }
+
+    impl Spatial2D for Named {
+        type Scalar = f32;
+        fn x(&self) -> f32 {self.pos[0]}
+        fn y(&self) -> f32 {self.pos[1]}
+    }
+
+    fn named(name: &str, x: f32, y: f32) -> Named {
+        Named { name: name.to_string(), pos: [x, y] }
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let a = tree.insert(Point2D::new(10., 10.)).unwrap();
+        let b = tree.insert(Point2D::new(10., 10.)).unwrap();
+        let c = tree.insert(Point2D::new(60., 60.)).unwrap();
+        assert_eq!(tree.insert(Point2D::new(160., 60.)), Err(SpatialError::OutOfBounds {
+            point: [160., 60., 0.],
+            bounds: [(0., 100.), (0., 100.), (0., 0.)],
+        }));
+
+        assert_eq!(tree.get(a), Some(&Point2D::new(10., 10.)));
+        tree.get_mut(c).unwrap().x = 61.;
+        assert_eq!(tree.get(c), Some(&Point2D::new(61., 60.)));
+
+        // duplicates are removed one at a time
+        assert_eq!(tree.remove_by_key(b), Some(Point2D::new(10., 10.)));
+        assert!(!tree.contains_key(b));
+        assert!(tree.contains_key(a));
+        assert!(tree.contains(&Point2D::new(10., 10.)));
+        assert_eq!(tree.remove_by_key(b), None);
+
+        assert_eq!(tree.remove_by_key(a), Some(Point2D::new(10., 10.)));
+        assert!(!tree.contains(&Point2D::new(10., 10.)));
+        assert_eq!(tree.values().count(), 1);
+    }
+
+    #[test]
+    fn test_update() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let a = tree.insert(Point2D::new(10., 10.)).unwrap();
+        let b = tree.insert(Point2D::new(12., 12.)).unwrap();
+        let c = tree.insert(Point2D::new(90., 90.)).unwrap();
+        let shape = tree.bounds();
+
+        // stays within its leaf
+        assert_eq!(tree.update(c, Point2D::new(80., 80.)).unwrap(), Some(Point2D::new(90., 90.)));
+        assert_eq!(tree.bounds(), shape);
+
+        // leaves its leaf; the branch that separated it from `b` collapses
+        tree.update(a, Point2D::new(60., 10.)).unwrap();
+        assert_eq!(tree.within(&Point2D::new(60., 10.), 1.), vec![&Point2D::new(60., 10.)]);
+        assert_eq!(tree.bounds().len(), 4);
+
+        assert!(tree.update(b, Point2D::new(120., 10.)).is_err());
+        assert_eq!(tree.get(b), Some(&Point2D::new(12., 12.)));
+
+        let outside = tree.move_all(|_, p| p.x += 30.);
+        assert_eq!(outside, vec![(c, Point2D::new(110., 80.))]);
+        assert_eq!(tree.get(c), None);
+        assert_eq!(tree.get(a), Some(&Point2D::new(90., 10.)));
+        assert_eq!(tree.get(b), Some(&Point2D::new(42., 12.)));
+        assert_eq!(tree.closest(&Point2D::new(40., 10.)), Some(&Point2D::new(42., 12.)));
+
+        // items moved through get_mut are placed again once the guard drops
+        tree.get_mut(a).unwrap().x = 10.;
+        assert_eq!(tree.within(&Point2D::new(10., 10.), 1.), vec![&Point2D::new(10., 10.)]);
+        assert_eq!(tree.update(a, Point2D::new(20., 20.)).unwrap(), Some(Point2D::new(10., 10.)));
+        assert_eq!(tree.within(&Point2D::new(20., 20.), 1.), vec![&Point2D::new(20., 20.)]);
+        tree.get_mut(b).unwrap().x = 90.;
+        assert_eq!(tree.closest(&Point2D::new(88., 12.)), Some(&Point2D::new(90., 12.)));
+        assert_eq!(tree.remove_by_key(b), Some(Point2D::new(90., 12.)));
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 100., 0., 100.)).count(), 1);
+
+        // and removed if they leave the bounds of a tree that does not grow
+        tree.get_mut(a).unwrap().y = 120.;
+        assert_eq!(tree.get(a), None);
+        assert!(tree.bounds().is_empty());
+    }
+
+    #[test]
+    fn test_within() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        tree.insert(Point2D::new(10., 10.)).unwrap();
+        tree.insert(Point2D::new(12., 10.)).unwrap();
+        tree.insert(Point2D::new(50., 50.)).unwrap();
+        tree.insert(Point2D::new(90., 90.)).unwrap();
+
+        let mut found = tree.within(&Point2D::new(11., 10.), 2.);
+        found.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        assert_eq!(found, vec![&Point2D::new(10., 10.), &Point2D::new(12., 10.)]);
+
+        assert_eq!(tree.within(&Point2D::new(50., 55.), 5.), vec![&Point2D::new(50., 50.)]);
+        assert!(tree.within(&Point2D::new(50., 56.), 5.).is_empty());
+        assert!(tree.within(&Point2D::new(-50., -50.), 10.).is_empty());
+        assert_eq!(tree.within(&Point2D::new(50., 50.), 200.).len(), 4);
+    }
+
+    #[test]
+    fn test_closest() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        assert_eq!(tree.closest(&Point2D::new(50., 50.)), None);
+
+        tree.insert(Point2D::new(10., 10.)).unwrap();
+        tree.insert(Point2D::new(12., 10.)).unwrap();
+        tree.insert(Point2D::new(60., 60.)).unwrap();
+        tree.insert(Point2D::new(90., 90.)).unwrap();
+
+        assert_eq!(tree.closest(&Point2D::new(0., 0.)), Some(&Point2D::new(10., 10.)));
+        assert_eq!(tree.closest(&Point2D::new(49., 51.)), Some(&Point2D::new(60., 60.)));
+        assert_eq!(tree.closest(&Point2D::new(200., 200.)), Some(&Point2D::new(90., 90.)));
+
+        // the query item itself is skipped
+        assert_eq!(tree.closest(&Point2D::new(10., 10.)), Some(&Point2D::new(12., 10.)));
+    }
+
+    #[test]
+    fn test_query_bounds() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        for i in 0..10 {
+            for j in 0..10 {
+                tree.insert(Point2D::new(i as f32 * 10. + 5., j as f32 * 10. + 5.)).unwrap();
+            }
+        }
+
+        let mut found: Vec<Point2D> = tree.query_bounds(&Bounds::new(20., 40., 60., 75.)).cloned().collect();
+        found.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+        assert_eq!(found, vec![
+            Point2D::new(25., 65.), Point2D::new(25., 75.),
+            Point2D::new(35., 65.), Point2D::new(35., 75.),
+        ]);
+
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 100., 0., 100.)).count(), 100);
+        assert_eq!(tree.query_bounds(&Bounds::new(1., 4., 1., 4.)).count(), 0);
+        assert_eq!(tree.query_bounds(&Bounds::new(200., 300., 0., 100.)).count(), 0);
+    }
+
+    #[test]
+    fn test_k_nearest() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        for i in 0..10 {
+            tree.insert(Point2D::new(i as f32 * 10., 0.)).unwrap();
+        }
+
+        let found = tree.k_nearest(&Point2D::new(31., 0.), 3, None);
+        assert_eq!(found, vec![
+            (&Point2D::new(30., 0.), 1.),
+            (&Point2D::new(40., 0.), 9.),
+            (&Point2D::new(20., 0.), 11.),
+        ]);
+
+        let found = tree.k_nearest(&Point2D::new(31., 0.), 3, Some(10.));
+        assert_eq!(found, vec![(&Point2D::new(30., 0.), 1.), (&Point2D::new(40., 0.), 9.)]);
+
+        assert_eq!(tree.k_nearest(&Point2D::new(31., 0.), 20, None).len(), 10);
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut tree = PointerQuadtree::builder(Bounds::new(0., 100., 0., 100.))
+            .leaf_capacity(4)
+            .max_depth(2)
+            .build();
+        let mut keys = vec![];
+        for i in 0..4 {
+            keys.push(tree.insert(Point2D::new(10. + i as f32, 10.)).unwrap());
+        }
+        // a single leaf holds up to four items
+        assert_eq!(tree.bounds().len(), 1);
+
+        // the fifth item splits the leaf down to the depth limit
+        keys.push(tree.insert(Point2D::new(14., 10.)).unwrap());
+        let types = tree.bounds_with_type();
+        assert_eq!(types.len(), 3);
+        assert!(matches!(types[2].1, BoundType::Saturated));
+
+        // removals merge the saturated node back into one leaf
+        tree.remove_by_key(keys[0]);
+        let types = tree.bounds_with_type();
+        assert_eq!(types.len(), 1);
+        assert!(matches!(types[0].1, BoundType::Leaf));
+
+        // nodes are not split below the minimum size
+        let mut tree = PointerQuadtree::builder(Bounds::new(0., 100., 0., 100.))
+            .min_size(30.)
+            .build();
+        tree.insert(Point2D::new(10., 10.)).unwrap();
+        tree.insert(Point2D::new(12., 10.)).unwrap();
+        tree.insert(Point2D::new(90., 90.)).unwrap();
+        assert_eq!(tree.bounds().len(), 3);
+        assert_eq!(tree.within(&Point2D::new(11., 10.), 2.).len(), 2);
+    }
+
+    #[test]
+    fn test_collapse() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        tree.insert(Point2D::new(10., 10.)).unwrap();
+        tree.insert(Point2D::new(11., 11.)).unwrap();
+        tree.insert(Point2D::new(90., 90.)).unwrap();
+
+        // the lone sibling is merged up into the root
+        assert_eq!(tree.remove(&Point2D::new(10., 10.)), Some(Point2D::new(10., 10.)));
+        tree.remove(&Point2D::new(90., 90.));
+        assert_eq!(tree.bounds(), vec![Bounds::new(0., 100., 0., 100.)]);
+
+        tree.remove(&Point2D::new(11., 11.));
+        assert!(tree.bounds().is_empty());
+
+        // branches left without items are removed by compact
+        tree.root = QuadtreeNode::new_branch();
+        tree.compact();
+        assert!(tree.bounds().is_empty());
+    }
+
+    #[test]
+    fn test_auto_grow() {
+        let mut tree = PointerQuadtree::builder(Bounds::new(0., 10., 0., 10.))
+            .auto_grow(true)
+            .build();
+        let items = [Point2D::new(0., 0.), Point2D::new(2., 2.), Point2D::new(8., 8.)];
+        for item in items.iter() {
+            tree.insert(*item).unwrap();
+        }
+        let far = tree.insert(Point2D::new(25., -5.)).unwrap();
+        assert_eq!(tree.bounds, Bounds::new(0., 40., -30., 10.));
+
+        // the point on the old corner now lies next to the old root
+        let mut fresh = PointerQuadtree::new(tree.bounds);
+        for item in items.iter().chain(tree.get(far)) {
+            fresh.insert(*item).unwrap();
+        }
+        let shape = tree.bounds();
+        assert_eq!(shape.len(), fresh.bounds().len());
+        assert!(fresh.bounds().iter().all(|b| shape.contains(b)));
+        assert_eq!(tree.query_bounds(&Bounds::new(-1., 1., -1., 1.)).count(), 1);
+
+        tree.update(far, Point2D::new(-5., 20.)).unwrap();
+        assert_eq!(tree.bounds, Bounds::new(-40., 40., -30., 50.));
+        assert_eq!(tree.k_nearest(&Point2D::new(-5., 19.), 1, None), vec![(&Point2D::new(-5., 20.), 1.)]);
+
+        let mut fixed = PointerQuadtree::new(Bounds::new(0., 10., 0., 10.));
+        assert!(fixed.insert(Point2D::new(25., -5.)).is_err());
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let points: Vec<Point2D> = (0..200)
+            .map(|i| Point2D::new((i * 37 % 100) as f32, (i * 11 % 100) as f32))
+            .collect();
+        let mut inserted = PointerQuadtree::builder(Bounds::new(0., 100., 0., 100.))
+            .leaf_capacity(4)
+            .build();
+        for p in &points {
+            inserted.insert(*p).unwrap();
+        }
+
+        let mut loaded = PointerQuadtree::builder(Bounds::new(0., 100., 0., 100.))
+            .leaf_capacity(4)
+            .build();
+        loaded.insert(points[0]).unwrap();
+        let keys = loaded.bulk_load(points[1..].iter().copied()).unwrap();
+        assert_eq!(loaded.get(keys[9]), Some(&points[10]));
+        assert_eq!(loaded.bounds(), inserted.bounds());
+
+        assert!(loaded.bulk_load(vec![Point2D::new(5., 5.), Point2D::new(150., 5.)]).is_err());
+        assert_eq!(loaded.values().count(), 200);
+
+        // items that do not fit are skipped
+        loaded.extend(vec![Point2D::new(5., 5.), Point2D::new(150., 5.)]);
+        assert_eq!(loaded.values().count(), 201);
+
+        // batches are inserted into the tree built so far
+        let mut extended = PointerQuadtree::builder(Bounds::new(0., 100., 0., 100.))
+            .leaf_capacity(4)
+            .build();
+        for batch in points.chunks(20) {
+            extended.extend(batch.iter().copied());
+        }
+        assert_eq!(extended.bounds(), inserted.bounds());
+
+        let collected: PointerQuadtree<Point2D> = points.iter().copied().collect();
+        assert_eq!(collected.bounds, Bounds::new(0., 99., 0., 99.));
+        assert_eq!(collected.values().count(), 200);
+    }
+
+    #[test]
+    fn test_owned_items() {
+        let mut tree = PointerQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let keys = tree.bulk_load((0..20).map(|i| named(&i.to_string(), i as f32 * 5. + 1., 50.))).unwrap();
+        assert_eq!(tree.get(keys[3]).unwrap().name, "3");
+        assert!(tree.contains(&named("7", 36., 50.)));
+        assert!(!tree.contains(&named("seven", 36., 50.)));
+        assert_eq!(tree.closest(&named("", 37., 50.)).unwrap().name, "7");
+
+        assert_eq!(tree.update(keys[7], named("seven", 90., 10.)), Ok(Some(named("7", 36., 50.))));
+        assert_eq!(tree.within(&[90., 10.], 1.), vec![&named("seven", 90., 10.)]);
+        assert_eq!(tree.remove(&named("seven", 90., 10.)), Some(named("seven", 90., 10.)));
+        assert_eq!(tree.remove(&named("seven", 90., 10.)), None);
+        assert_eq!(tree.get(keys[7]), None);
+        assert_eq!(tree.remove_by_key(keys[0]).map(|item| item.name), Some("0".to_string()));
+        assert_eq!(tree.values().count(), 18);
+    }
+
+    #[test]
+    fn test_f64() {
+        // projected coordinates a metre apart that f32 cannot tell apart
+        let mut tree = PointerQuadtree::new(Bounds::new(4e6_f64, 5e6, 4e6, 5e6));
+        tree.insert([4_500_000.25, 4_500_000.]).unwrap();
+        tree.insert([4_500_001.25, 4_500_000.]).unwrap();
+
+        assert!(tree.contains(&[4_500_001.25, 4_500_000.]));
+        assert!(!tree.contains(&[4_500_001., 4_500_000.]));
+        assert_eq!(tree.within(&[4_500_000., 4_500_000.], 0.5), vec![&[4_500_000.25, 4_500_000.]]);
+        assert_eq!(
+            tree.k_nearest(&[4_500_002., 4_500_000.], 1, None),
+            vec![(&[4_500_001.25, 4_500_000.], 0.75)]
+        );
+    }
+}
@@ -0,0 +1,5 @@
+#[allow(clippy::module_inception)]
+mod region_quadtree;
+
+pub use self::region_quadtree::RegionQuadtree as RegionQuadtree;
+pub use self::region_quadtree::QueryBounds as QueryBounds;
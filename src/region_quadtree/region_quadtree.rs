@@ -0,0 +1,300 @@
+use std::iter;
+use std::mem;
+use std::slice;
+use slotmap::DenseSlotMap;
+use slotmap::dense::Values;
+use crate::core::{Spatial2D, SpatialBounds2D, SpatialKey, AreaNode, Bounds, Scalar, Result, SpatialError};
+
+const MAX_RECURCION: u32 = 8;
+
+/// Quadtree over objects covering an area. Each object is stored in
+/// the smallest node that fully encloses its bounds
+#[derive(Debug)]
+pub struct RegionQuadtree<T>
+    where T: SpatialBounds2D {
+    container: DenseSlotMap<SpatialKey, T>,
+    root: AreaNode,
+    pub bounds: Bounds<T::Scalar>
+}
+
+impl<T> RegionQuadtree<T>
+    where T: SpatialBounds2D {
+    pub fn new(bounds: Bounds<T::Scalar>) -> Self {
+        RegionQuadtree {
+            container: DenseSlotMap::with_key(),
+            root: AreaNode::default(),
+            bounds
+        }
+    }
+}
+
+impl<T> RegionQuadtree<T>
+    where T: SpatialBounds2D {
+
+    /// Inserts the object and returns a key that stays valid until it is
+    /// removed. Fails if the bounds of the object are not inside the tree
+    pub fn insert(&mut self, item: T) -> Result<SpatialKey> {
+        let item_bounds = item.bounds();
+        if !self.bounds.contains_bounds(item_bounds) {
+            return Err(SpatialError::region_out_of_bounds(item_bounds, self.bounds));
+        }
+        let key = self.container.insert(item);
+        self.root.smallest_enclosing_mut(item_bounds, self.bounds, 0).items.push(key);
+        Ok(key)
+    }
+
+    pub fn get(&self, key: SpatialKey) -> Option<&T> {
+        self.container.get(key)
+    }
+
+    pub fn contains_key(&self, key: SpatialKey) -> bool {
+        self.container.contains_key(key)
+    }
+
+    /// Removes the object stored under `key` and returns it
+    pub fn remove(&mut self, key: SpatialKey) -> Option<T> {
+        let item = self.container.remove(key)?;
+        self.root.remove_enclosed(key, item.bounds(), self.bounds, 0);
+        Some(item)
+    }
+
+    /// Replaces the object stored under `key`, moving it to the node
+    /// enclosing its new bounds. Returns the previous object, or none
+    /// if the key is not in the tree
+    pub fn update(&mut self, key: SpatialKey, item: T) -> Result<Option<T>> {
+        let old_bounds = match self.container.get(key) {
+            Some(old) => old.bounds(),
+            None => return Ok(None)
+        };
+        let item_bounds = item.bounds();
+        if !self.bounds.contains_bounds(item_bounds) {
+            return Err(SpatialError::region_out_of_bounds(item_bounds, self.bounds));
+        }
+        self.root.remove_enclosed(key, old_bounds, self.bounds, 0);
+        let old = mem::replace(&mut self.container[key], item);
+        self.root.smallest_enclosing_mut(item_bounds, self.bounds, 0).items.push(key);
+        Ok(Some(old))
+    }
+
+    pub fn values(&self) -> Values<'_, SpatialKey, T> {
+        self.container.values()
+    }
+
+    /// Returns the bounds of every node in the tree
+    pub fn bounds(&self) -> Vec<Bounds<T::Scalar>> {
+        let mut vec = vec![];
+        if !self.root.is_empty() {
+            self.root.bounds(&mut vec, self.bounds);
+        }
+        vec
+    }
+
+    /// Returns an iterator over the objects whose bounds contain `p`
+    pub fn query_point(&self, p: &dyn Spatial2D<Scalar = T::Scalar>) -> QueryBounds<'_, T> {
+        let (x, y) = p.pos();
+        self.query_bounds(&Bounds::new(x, x, y, y))
+    }
+
+    /// Returns an iterator over the objects whose bounds intersect the
+    /// query region, including objects that only touch its edge
+    pub fn query_bounds(&self, query: &Bounds<T::Scalar>) -> QueryBounds<'_, T> {
+        let mut stack = vec![];
+        if self.bounds.intersects(*query) {
+            stack.push((&self.root, self.bounds));
+        }
+        QueryBounds {
+            container: &self.container,
+            query: *query,
+            stack,
+            pending: [].iter()
+        }
+    }
+
+    /// Returns the other objects sharing an area with the object stored
+    /// under `key`. Objects that only touch it are not returned
+    pub fn overlapping(&self, key: SpatialKey) -> Vec<(SpatialKey, &T)> {
+        let item_bounds = match self.container.get(key) {
+            Some(item) => item.bounds(),
+            None => return vec![]
+        };
+        let mut query = self.query_bounds(&item_bounds);
+        iter::from_fn(|| query.next_entry())
+            .filter(|&(other, item)| other != key && item.bounds().overlaps(item_bounds))
+            .collect()
+    }
+}
+
+/// Iterator over the objects intersecting a query region, created by
+/// [`RegionQuadtree::query_bounds`] and [`RegionQuadtree::query_point`]
+pub struct QueryBounds<'a, T>
+    where T: SpatialBounds2D {
+    container: &'a DenseSlotMap<SpatialKey, T>,
+    query: Bounds<T::Scalar>,
+    stack: Vec<(&'a AreaNode, Bounds<T::Scalar>)>,
+    pending: slice::Iter<'a, SpatialKey>,
+}
+
+impl<'a, T> QueryBounds<'a, T>
+    where T: SpatialBounds2D {
+    fn next_entry(&mut self) -> Option<(SpatialKey, &'a T)> {
+        loop {
+            for key in &mut self.pending {
+                let item = &self.container[*key];
+                if item.bounds().intersects(self.query) {
+                    return Some((*key, item));
+                }
+            }
+            let (node, curr_bound) = self.stack.pop()?;
+            self.pending = node.items.iter();
+            if let Some(branch) = &node.children {
+                for &(child, quadrant) in branch.children().iter() {
+                    let child_bound = curr_bound.sub_bound(quadrant);
+                    if !child.is_empty() && child_bound.intersects(self.query) {
+                        self.stack.push((child, child_bound));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for QueryBounds<'a, T>
+    where T: SpatialBounds2D {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().map(|(_, item)| item)
+    }
+}
+
+impl AreaNode {
+    /// Returns the deepest node whose bounds fully contain `test_bound`,
+    /// creating the nodes on the way down
+    fn smallest_enclosing_mut<S>(
+        &mut self,
+        test_bound: Bounds<S>,
+        curr_bound: Bounds<S>,
+        r_lvl: u32
+    ) -> &mut AreaNode
+        where S: Scalar {
+        if r_lvl >= MAX_RECURCION {
+            return self;
+        }
+        match curr_bound.enclosing_quadrant(test_bound) {
+            Some(quadrant) => self.children
+                .get_or_insert_with(Default::default)
+                .child_mut(quadrant)
+                .smallest_enclosing_mut(test_bound, curr_bound.sub_bound(quadrant), r_lvl + 1),
+            None => self
+        }
+    }
+
+    /// Removes `key` from the node enclosing `test_bound`, dropping
+    /// children on the way back up once they hold nothing
+    fn remove_enclosed<S>(
+        &mut self,
+        key: SpatialKey,
+        test_bound: Bounds<S>,
+        curr_bound: Bounds<S>,
+        r_lvl: u32
+    ) -> bool
+        where S: Scalar {
+        let quadrant = if r_lvl < MAX_RECURCION {
+            curr_bound.enclosing_quadrant(test_bound)
+        } else {
+            None
+        };
+        let removed = match (quadrant, &mut self.children) {
+            (Some(quadrant), Some(branch)) => branch.child_mut(quadrant)
+                .remove_enclosed(key, test_bound, curr_bound.sub_bound(quadrant), r_lvl + 1),
+            (Some(_), None) => false,
+            (None, _) => self.remove_item(key)
+        };
+        if removed {
+            self.prune();
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RegionQuadtree;
+    use crate::core::{Bounds, Point2D, SpatialBounds2D};
+
+    #[test]
+    fn test_insert() {
+        let mut tree = RegionQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        // crosses the center, so it stays in the root
+        let a = tree.insert(Bounds::new(40., 60., 40., 60.)).unwrap();
+        assert_eq!(tree.bounds(), vec![Bounds::new(0., 100., 0., 100.)]);
+
+        let b = tree.insert(Bounds::new(55., 70., 10., 20.)).unwrap();
+        assert_eq!(tree.bounds().len(), 3);
+        assert!(tree.insert(Bounds::new(90., 110., 10., 20.)).is_err());
+
+        assert_eq!(tree.remove(b), Some(Bounds::new(55., 70., 10., 20.)));
+        assert_eq!(tree.bounds().len(), 1);
+        assert_eq!(tree.remove(b), None);
+        assert!(tree.contains_key(a));
+
+        // the first node too small to enclose it is at depth 6
+        tree.update(a, Bounds::new(1., 2., 1., 2.)).unwrap();
+        assert_eq!(tree.bounds().len(), 6);
+        assert!(tree.update(a, Bounds::new(-1., 2., 1., 2.)).is_err());
+        assert_eq!(tree.get(a), Some(&Bounds::new(1., 2., 1., 2.)));
+
+        tree.remove(a);
+        assert!(tree.bounds().is_empty());
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Label {
+        text: String,
+        area: Bounds,
+    }
+
+    impl SpatialBounds2D for Label {
+        type Scalar = f32;
+        fn bounds(&self) -> Bounds {self.area}
+    }
+
+    #[test]
+    fn test_owned_items() {
+        let mut tree = RegionQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let label = |text: &str, area| Label { text: text.to_string(), area };
+        let a = tree.insert(label("a", Bounds::new(10., 20., 10., 20.))).unwrap();
+        tree.insert(label("b", Bounds::new(40., 60., 40., 60.))).unwrap();
+
+        let old = tree.update(a, label("moved", Bounds::new(70., 80., 70., 80.))).unwrap();
+        assert_eq!(old, Some(label("a", Bounds::new(10., 20., 10., 20.))));
+        let found: Vec<&str> = tree.query_point(&Point2D::new(75., 75.)).map(|l| l.text.as_str()).collect();
+        assert_eq!(found, vec!["moved"]);
+        assert_eq!(tree.query_point(&Point2D::new(15., 15.)).count(), 0);
+        assert_eq!(tree.remove(a).map(|l| l.text), Some("moved".to_string()));
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut tree = RegionQuadtree::new(Bounds::new(0., 100., 0., 100.));
+        let a = tree.insert(Bounds::new(40., 60., 40., 60.)).unwrap();
+        let b = tree.insert(Bounds::new(10., 45., 10., 45.)).unwrap();
+        let c = tree.insert(Bounds::new(60., 70., 60., 70.)).unwrap();
+        tree.insert(Bounds::new(80., 90., 10., 20.)).unwrap();
+
+        let mut found: Vec<_> = tree.query_point(&Point2D::new(42., 42.)).cloned().collect();
+        found.sort_by(|l, r| l.x_min.partial_cmp(&r.x_min).unwrap());
+        assert_eq!(found, vec![Bounds::new(10., 45., 10., 45.), Bounds::new(40., 60., 40., 60.)]);
+        assert_eq!(tree.query_point(&Point2D::new(95., 95.)).count(), 0);
+
+        assert_eq!(tree.query_bounds(&Bounds::new(75., 95., 0., 50.)).count(), 1);
+        // touching edges count for window queries
+        assert_eq!(tree.query_bounds(&Bounds::new(70., 75., 65., 75.)).count(), 1);
+        assert_eq!(tree.query_bounds(&Bounds::new(0., 100., 0., 100.)).count(), 4);
+
+        // but not for object overlap
+        let overlapping: Vec<_> = tree.overlapping(a).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(overlapping, vec![b]);
+        assert!(tree.overlapping(c).is_empty());
+    }
+}
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+mod spatial_map;
+
+pub use self::spatial_map::SpatialMap as SpatialMap;
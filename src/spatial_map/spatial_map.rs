@@ -0,0 +1,159 @@
+use std::marker::PhantomData;
+use slotmap::SecondaryMap;
+use crate::core::{Spatial2D, SpatialKey, SpatialIndex, Bounds, Scalar, Result};
+use crate::linear_quadtree::LinearQuadtree;
+
+/// Map from positions to values, layered on one of the point indices of
+/// this crate. Values need no trait of their own, and the same value type
+/// can be stored under any position.
+///
+/// Several values may share a position, so every value is addressed
+/// by the persistent key returned when inserting it. The index only
+/// holds the positions, and the values are stored under the same keys
+pub struct SpatialMap<V, S = f32, I = LinearQuadtree<[S; 2]>>
+    where S: Scalar {
+    index: I,
+    values: SecondaryMap<SpatialKey, V>,
+    marker: PhantomData<[S; 2]>,
+}
+
+impl<V, S> SpatialMap<V, S>
+    where S: Scalar {
+    /// Creates a map backed by a [`LinearQuadtree`] covering `bounds`
+    pub fn new(bounds: Bounds<S>) -> Self {
+        SpatialMap::with_index(LinearQuadtree::new(bounds))
+    }
+}
+
+impl<V, S, I> SpatialMap<V, S, I>
+    where S: Scalar, I: SpatialIndex<Item = [S; 2]> {
+    /// Creates a map that stores the positions of its values in `index`.
+    /// Positions already in `index` have no value and are skipped
+    pub fn with_index(index: I) -> Self {
+        SpatialMap {
+            index,
+            values: SecondaryMap::new(),
+            marker: PhantomData
+        }
+    }
+
+    /// Returns the index holding the positions
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+
+    /// Inserts `value` at `position` and returns a persistent key that
+    /// indexes it, or an error if the index can not hold the position
+    pub fn insert<P>(&mut self, position: P, value: V) -> Result<SpatialKey>
+        where P: Spatial2D<Scalar = S> {
+        let key = self.index.insert([position.x(), position.y()])?;
+        self.values.insert(key, value);
+        Ok(key)
+    }
+
+    pub fn get(&self, key: SpatialKey) -> Option<(&[S; 2], &V)> {
+        self.pair(key, self.index.get(key)?)
+    }
+
+    /// Returns a mutable reference to the value at `key`
+    pub fn get_mut(&mut self, key: SpatialKey) -> Option<&mut V> {
+        self.values.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: SpatialKey) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Removes the value at `key` and returns it along with its position
+    pub fn remove(&mut self, key: SpatialKey) -> Option<([S; 2], V)> {
+        let value = self.values.remove(key)?;
+        let position = self.index.remove_by_key(key)?;
+        Some((position, value))
+    }
+
+    /// Returns the number of stored values
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns an iterator over every value and its position, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&[S; 2], &V)> + '_ {
+        self.index.iter().filter_map(move |(key, position)| self.pair(key, position))
+    }
+
+    /// Returns an iterator over every value, in no particular order
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.values.values()
+    }
+
+    /// Returns an iterator over every value inside `query` and its position
+    pub fn query_bounds(&self, query: &Bounds<S>) -> impl Iterator<Item = (&[S; 2], &V)> + '_ {
+        self.index.query_bounds_keyed(query).filter_map(move |(key, position)| self.pair(key, position))
+    }
+
+    /// Returns every value whose position is at most `radius` away from `p`,
+    /// sorted from nearest to farthest
+    pub fn within(&self, p: &dyn Spatial2D<Scalar = S>, radius: S) -> Vec<(&[S; 2], &V)> {
+        self.index.within_keyed(p, radius).into_iter()
+            .filter_map(|(key, position)| self.pair(key, position))
+            .collect()
+    }
+
+    /// Returns up to `k` values closest to `p` along with their positions
+    /// and distances, sorted from nearest to farthest
+    pub fn k_nearest(&self, p: &dyn Spatial2D<Scalar = S>, k: usize) -> Vec<(&[S; 2], &V, S)> {
+        self.index.k_nearest_keyed(p, k).into_iter()
+            .filter_map(|(key, position, distance)| Some((position, self.values.get(key)?, distance)))
+            .collect()
+    }
+
+    fn pair<'a>(&'a self, key: SpatialKey, position: &'a [S; 2]) -> Option<(&'a [S; 2], &'a V)> {
+        Some((position, self.values.get(key)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpatialMap;
+    use crate::core::{Bounds, Point2D};
+    use crate::pointer_quadtree::PointerQuadtree;
+
+    #[test]
+    fn test_spatial_map() {
+        let mut map = SpatialMap::new(Bounds::new(0., 100., 0., 100.));
+        let a = map.insert([10., 10.], "a".to_string()).unwrap();
+        let b = map.insert(Point2D::new(10., 10.), "b".to_string()).unwrap();
+        let c = map.insert([60., 60.], "c".to_string()).unwrap();
+        assert!(map.insert([160., 60.], "d".to_string()).is_err());
+        assert_eq!(map.len(), 3);
+
+        assert_eq!(map.get(a), Some((&[10., 10.], &"a".to_string())));
+        map.get_mut(b).unwrap().push('!');
+        let mut found: Vec<&String> = map.within(&[11., 10.], 2.).into_iter()
+            .map(|(_, value)| value)
+            .collect();
+        found.sort();
+        assert_eq!(found, vec!["a", "b!"]);
+        assert_eq!(map.k_nearest(&[60., 61.], 1), vec![(&[60., 60.], &"c".to_string(), 1.)]);
+        assert_eq!(map.query_bounds(&Bounds::new(50., 70., 50., 70.)).count(), 1);
+
+        assert_eq!(map.remove(c), Some(([60., 60.], "c".to_string())));
+        assert!(!map.contains_key(c));
+        assert_eq!(map.values().count(), 2);
+    }
+
+    #[test]
+    fn test_with_index() {
+        let mut map = SpatialMap::with_index(PointerQuadtree::new(Bounds::new(0., 100., 0., 100.)));
+        for i in 0..10 {
+            map.insert([i as f32 * 10. + 5., 50.], vec![i; i]).unwrap();
+        }
+        assert_eq!(map.k_nearest(&[36., 50.], 1), vec![(&[35., 50.], &vec![3; 3], 1.)]);
+        assert_eq!(map.iter().map(|(_, value)| value.len()).sum::<usize>(), 45);
+        assert_eq!(map.index().values().count(), 10);
+    }
+}